@@ -16,3 +16,4 @@ fi
 $SUDO apt-get update && $SUDO apt-get install --assume-yes \
 	libgtk-3-dev${CROSS_DEB_ARCH:+:$CROSS_DEB_ARCH} \
 	libdbusmenu-gtk3-dev${CROSS_DEB_ARCH:+:$CROSS_DEB_ARCH} \
+	dbus
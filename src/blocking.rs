@@ -0,0 +1,253 @@
+use crate::client::{self, ActivateRequest, ClientBuilder, Event};
+use crate::error::Result;
+use crate::menu::TrayMenu;
+use std::thread::JoinHandle;
+use tokio::runtime::Handle;
+use tokio::sync::{broadcast, oneshot};
+use tokio::task;
+use tracing::warn;
+
+#[cfg(feature = "data")]
+use crate::item::StatusNotifierItem;
+
+/// Blocking wrapper around [`client::Client`],
+/// for consumers without a Tokio runtime.
+///
+/// The async client runs on a runtime driven by a dedicated background thread,
+/// which stops when this is dropped.
+///
+/// The methods on this type block the calling thread,
+/// so must not be called from within an async context.
+#[derive(Debug)]
+pub struct Client {
+    inner: client::Client,
+    rx: broadcast::Receiver<Event>,
+    /// The number of events skipped since [`Client::skipped_events`] was last called.
+    skipped: u64,
+    handle: Handle,
+    shutdown: Option<oneshot::Sender<task::JoinHandle<Result<()>>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Client {
+    /// Creates and initializes the client.
+    ///
+    /// See [`client::Client::new`] for details.
+    ///
+    /// # Errors
+    ///
+    /// If the background thread cannot be started,
+    /// or the initialization fails for any reason,
+    /// this method will return an error.
+    pub fn new() -> Result<Self> {
+        Self::with_builder(client::Client::builder())
+    }
+
+    /// Creates and initializes the client using the options configured on `builder`.
+    ///
+    /// # Errors
+    ///
+    /// If the background thread cannot be started,
+    /// or the initialization fails for any reason,
+    /// this method will return an error.
+    pub fn with_builder(builder: ClientBuilder) -> Result<Self> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let handle = runtime.handle().clone();
+        let (shutdown, shutdown_rx) = oneshot::channel();
+
+        let thread = std::thread::Builder::new()
+            .name("system-tray".to_string())
+            .spawn(move || {
+                runtime.block_on(async {
+                    // the channel is closed without a release if the client fails to start
+                    let Ok(release) = shutdown_rx.await else {
+                        return;
+                    };
+
+                    // keep the runtime running until the name is released
+                    match release.await {
+                        Ok(Err(err)) => warn!("failed to release host name: {err}"),
+                        Err(err) => warn!("failed to release host name: {err}"),
+                        Ok(Ok(())) => {}
+                    }
+                });
+            })?;
+
+        let inner = handle.block_on(builder.build());
+
+        let inner = match inner {
+            Ok(inner) => inner,
+            Err(err) => {
+                drop(shutdown);
+                let _ = thread.join();
+                return Err(err);
+            }
+        };
+
+        let rx = inner.subscribe();
+
+        Ok(Self {
+            inner,
+            rx,
+            skipped: 0,
+            handle,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    /// Blocks until at least one event is received,
+    /// then returns it along with any others already received, in order.
+    ///
+    /// Returns `None` once the client has stopped.
+    /// If events are not received quickly enough, the oldest are skipped.
+    /// See [`Client::skipped_events`].
+    pub fn next_event(&mut self) -> Option<Vec<Event>> {
+        loop {
+            match self.rx.blocking_recv() {
+                Ok(event) => {
+                    let mut events = vec![event];
+                    self.drain_into(&mut events);
+                    break Some(events);
+                }
+                Err(broadcast::error::RecvError::Lagged(count)) => self.skip(count),
+                Err(broadcast::error::RecvError::Closed) => break None,
+            }
+        }
+    }
+
+    /// Gets the events which have already been received, in order, without blocking.
+    ///
+    /// Returns `None` if there are none.
+    /// This is intended for polling from another event loop,
+    /// such as an idle callback on the `GLib` main loop.
+    pub fn try_next_event(&mut self) -> Option<Vec<Event>> {
+        let mut events = vec![];
+        self.drain_into(&mut events);
+        (!events.is_empty()).then_some(events)
+    }
+
+    /// Gets the number of events skipped since this was last called,
+    /// because they were not received quickly enough.
+    ///
+    /// The capacity of the events channel can be raised with
+    /// [`ClientBuilder::capacity`] to avoid this.
+    pub fn skipped_events(&mut self) -> u64 {
+        std::mem::take(&mut self.skipped)
+    }
+
+    fn drain_into(&mut self, events: &mut Vec<Event>) {
+        loop {
+            match self.rx.try_recv() {
+                Ok(event) => events.push(event),
+                Err(broadcast::error::TryRecvError::Lagged(count)) => self.skip(count),
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn skip(&mut self, count: u64) {
+        warn!("skipped {count} events");
+        self.skipped += count;
+    }
+
+    /// Gets all current items, including their menus if present.
+    ///
+    /// See [`client::Client::items`].
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn items(&self) -> std::sync::Arc<std::sync::Mutex<crate::data::BaseMap>> {
+        self.inner.items()
+    }
+
+    /// Gets a single item and its menu, if present.
+    ///
+    /// See [`client::Client::get_item`].
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn get_item(&self, address: &str) -> Option<(StatusNotifierItem, Option<TrayMenu>)> {
+        self.inner.get_item(address)
+    }
+
+    /// One should call this method before displaying the menu item.
+    ///
+    /// See [`client::Client::about_to_show_menuitem`].
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created.
+    pub fn about_to_show_menuitem(
+        &self,
+        address: String,
+        menu_path: String,
+        id: i32,
+    ) -> Result<bool> {
+        self.handle
+            .block_on(self.inner.about_to_show_menuitem(address, menu_path, id))
+    }
+
+    /// Calls [`Client::about_to_show_menuitem`] for several menu items in a single call.
+    ///
+    /// See [`client::Client::about_to_show_group`].
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created.
+    pub fn about_to_show_group(
+        &self,
+        address: String,
+        menu_path: String,
+        ids: Vec<i32>,
+    ) -> Result<(Vec<i32>, Vec<i32>)> {
+        self.handle
+            .block_on(self.inner.about_to_show_group(address, menu_path, ids))
+    }
+
+    /// Fetches the direct children of the menu item with id `parent_id`.
+    ///
+    /// See [`client::Client::get_submenu`].
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created,
+    /// or if the returned layout is invalid.
+    pub fn get_submenu(
+        &self,
+        address: String,
+        menu_path: String,
+        parent_id: i32,
+    ) -> Result<TrayMenu> {
+        self.handle
+            .block_on(self.inner.get_submenu(address, menu_path, parent_id))
+    }
+
+    /// Sends an activate request for a menu item.
+    ///
+    /// See [`client::Client::activate`].
+    ///
+    /// # Errors
+    ///
+    /// The method will return an error if the connection to the `DBus` object fails,
+    /// or if sending the event fails for any reason.
+    pub fn activate(&self, req: ActivateRequest) -> Result<()> {
+        self.handle.block_on(self.inner.activate(req))
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        // the runtime thread waits for the name to be released before stopping,
+        // so this does not need to block on the runtime itself
+        if let Some(shutdown) = self.shutdown.take() {
+            let release = self.handle.spawn(self.inner.release_host_name());
+            let _ = shutdown.send(release);
+        }
+
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -1,25 +1,35 @@
-#[cfg(feature = "data")]
-use crate::data::apply_menu_diffs;
 use crate::data::TrayItemMap;
 use crate::dbus::dbus_menu_proxy::{DBusMenuProxy, PropertiesUpdate};
 use crate::dbus::notifier_item_proxy::StatusNotifierItemProxy;
-use crate::dbus::notifier_watcher_proxy::StatusNotifierWatcherProxy;
+use crate::dbus::notifier_watcher_proxy::{
+    StatusNotifierItemRegisteredStream, StatusNotifierWatcherProxy,
+};
 use crate::dbus::status_notifier_watcher::StatusNotifierWatcher;
 use crate::dbus::{self, OwnedValueExt};
 use crate::error::{Error, Result};
-use crate::item::{self, IconPixmap, Status, StatusNotifierItem, Tooltip};
-use crate::menu::{MenuDiff, TrayMenu};
+use crate::item::{self, Category, IconPixmap, Status, StatusNotifierItem, Tooltip};
+use crate::menu::{
+    MenuDiff, MenuEventType, MenuItem, MenuRevision, MenuStatus, TextDirection, TrayMenu,
+    MENU_PROPERTIES,
+};
 use crate::names;
+use crate::queue::EventQueue;
+use crate::sync::MutexExt;
 use dbus::DBusProps;
-use futures_lite::StreamExt;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::spawn;
-use tokio::sync::broadcast;
+use futures_lite::future::poll_once;
+use futures_lite::{Stream, StreamExt};
+use std::collections::hash_map::{Entry, HashMap};
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::sync::{broadcast, oneshot};
+use tokio::task::{AbortHandle, JoinSet};
 use tokio::time::timeout;
 use tracing::{debug, error, trace, warn};
-use zbus::fdo::{DBusProxy, PropertiesProxy};
-use zbus::names::InterfaceName;
-use zbus::zvariant::{Array, Structure, Value};
+use zbus::fdo::{DBusProxy, NameOwnerChangedStream, PropertiesProxy};
+use zbus::names::{InterfaceName, OwnedWellKnownName};
+use zbus::proxy::{CacheProperties, SignalStream};
+use zbus::zvariant::{Array, OwnedObjectPath, OwnedValue, Structure, Value};
 use zbus::{Connection, Message};
 
 use self::names::ITEM_OBJECT;
@@ -27,19 +37,105 @@ use self::names::ITEM_OBJECT;
 /// An event emitted by the client
 /// representing a change from either the `StatusNotifierItem`
 /// or `DBusMenu` protocols.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 pub enum Event {
     /// A new `StatusNotifierItem` was added.
-    Add(String, Box<StatusNotifierItem>),
+    Add {
+        /// The address the item is reported under in other events.
+        address: String,
+        item: Box<StatusNotifierItem>,
+        /// Whether the item was already registered when the client started or reconnected,
+        /// in which case it is sent before [`Event::Ready`]
+        /// unless the item did not respond straight away.
+        /// This is `false` for items which registered afterwards,
+        /// which lets UIs animate items as they appear without animating the initial batch.
+        initial: bool,
+    },
     /// An update was received for an existing `StatusNotifierItem`.
     /// This could be either an update to the item itself,
     /// or an update to the associated menu.
     Update(String, UpdateEvent),
     /// A `StatusNotifierItem` was unregistered.
     Remove(String),
+    /// All items which were registered when the client started have been loaded.
+    ///
+    /// Items which do not respond to the first request for their properties
+    /// do not hold this up, and are added once they respond
+    /// or are given up on, see [`ClientBuilder::item_retries`].
+    /// This is sent again after reconnecting, once rediscovered items have been loaded.
+    Ready,
+    /// Sent periodically when [`ClientBuilder::heartbeat_interval`] is set,
+    /// regardless of whether anything has changed.
+    ///
+    /// This can be used as a watchdog to check that events are still flowing.
+    Heartbeat,
+    /// Whether any host is registered on the watcher has changed.
+    ///
+    /// Only sent when enabled with [`ClientBuilder::host_registration_events`].
+    HostRegistrationChanged(bool),
+    /// A background task stopped because of an error,
+    /// such as losing the connection to the bus.
+    ///
+    /// Errors which are retried, or which only affect a single request,
+    /// are not sent.
+    /// This is meant for surfacing failures which stop the client from updating,
+    /// so the message is for display or logging.
+    Error(String),
+    /// An item was registered, but its properties are invalid,
+    /// for example because it is missing its id.
+    ///
+    /// The item is not added.
+    Malformed {
+        /// The address the item registered under.
+        address: String,
+        /// Why the item's properties could not be parsed.
+        reason: String,
+    },
+}
+
+impl Event {
+    /// Gets a stable name for the kind of event,
+    /// such as `add` or `update`, for use in logging and metrics.
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Add { .. } => "add",
+            Self::Update(..) => "update",
+            Self::Remove(_) => "remove",
+            Self::Ready => "ready",
+            Self::Heartbeat => "heartbeat",
+            Self::HostRegistrationChanged(_) => "host-registration-changed",
+            Self::Malformed { .. } => "malformed",
+            Self::Error(_) => "error",
+        }
+    }
+}
+
+/// An [`Event`] tagged with a sequence number.
+///
+/// See [`Client::subscribe_sequenced`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct EventEnvelope {
+    /// Strictly increasing for each event sent by the client.
+    ///
+    /// If events are dropped because a receiver lagged behind,
+    /// the sequence number skips the dropped events,
+    /// so gaps can be detected by checking for a difference greater than 1.
+    pub seq: u64,
+    /// When the client sent the event, taken as it was sequenced.
+    ///
+    /// An [`Instant`] only has meaning within the process which took it,
+    /// so this is not serialized, and is `None` for deserialized envelopes.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub sent_at: Option<Instant>,
+    /// The event itself.
+    pub event: Event,
 }
 
 /// The specific change associated with an update event.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 pub enum UpdateEvent {
     AttentionIcon(Option<String>),
@@ -49,6 +145,11 @@ pub enum UpdateEvent {
     },
     OverlayIcon(Option<String>),
     Status(Status),
+    /// The item's title has changed.
+    ///
+    /// An explicitly empty title is `Some("")`.
+    /// `None` means the item no longer has a title,
+    /// or it could not be fetched.
     Title(Option<String>),
     Tooltip(Option<Tooltip>),
     /// A menu layout has changed.
@@ -57,13 +158,80 @@ pub enum UpdateEvent {
     /// One or more menu properties have changed.
     /// Only the updated properties are sent.
     MenuDiff(Vec<MenuDiff>),
+    /// Part of a menu layout has changed.
+    /// The children of the item with id `parent_id` are sent,
+    /// and replace its existing children.
+    ///
+    /// See [`TrayMenu::apply_subtree`].
+    MenuSubtree {
+        parent_id: i32,
+        menu: TrayMenu,
+    },
+    /// The menu's `TextDirection` property has changed.
+    ///
+    /// The current value is also included in each [`UpdateEvent::Menu`].
+    MenuTextDirection(TextDirection),
+    /// The menu's `Status` property has changed.
+    ///
+    /// The current value is also included in each [`UpdateEvent::Menu`].
+    MenuStatus(MenuStatus),
     /// A new menu has connected to the item.
     /// Its name on bus is sent.
+    ///
+    /// This is also sent if the item's menu path changes,
+    /// followed by a [`UpdateEvent::Menu`] with the new layout.
     MenuConnect(String),
+    /// The item's menu has gone away,
+    /// but the item itself remains.
+    ///
+    /// There is no signal for this,
+    /// so it is checked for whenever any other property changes.
+    MenuDisconnect,
+    /// The item's `ItemIsMenu` property has changed.
+    ///
+    /// There is no signal for this property,
+    /// so it is polled whenever any other property changes.
+    ItemIsMenu(bool),
+    /// A property not known to this crate has changed,
+    /// such as a vendor extension.
+    ///
+    /// This is sent for any signal named `New{name}` without a matching variant,
+    /// with the value of the property `name`.
+    /// Any vendor prefix is kept, so `XAyatanaNewLabel` gives `XAyatanaLabel`.
+    Other {
+        name: String,
+        value: OwnedValue,
+    },
+}
+
+impl UpdateEvent {
+    /// Gets a stable name for the kind of update,
+    /// such as `icon` or `menu-diff`, for use in logging and metrics.
+    #[must_use]
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::AttentionIcon(_) => "attention-icon",
+            Self::Icon { .. } => "icon",
+            Self::OverlayIcon(_) => "overlay-icon",
+            Self::Status(_) => "status",
+            Self::Title(_) => "title",
+            Self::Tooltip(_) => "tooltip",
+            Self::Menu(_) => "menu",
+            Self::MenuDiff(_) => "menu-diff",
+            Self::MenuSubtree { .. } => "menu-subtree",
+            Self::MenuTextDirection(_) => "menu-text-direction",
+            Self::MenuStatus(_) => "menu-status",
+            Self::MenuConnect(_) => "menu-connect",
+            Self::MenuDisconnect => "menu-disconnect",
+            Self::ItemIsMenu(_) => "item-is-menu",
+            Self::Other { .. } => "other",
+        }
+    }
 }
 
 /// A request to 'activate' one of the menu items,
 /// typically sent when it is clicked.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 pub enum ActivateRequest {
     /// Submenu ID
@@ -72,6 +240,17 @@ pub enum ActivateRequest {
         menu_path: String,
         submenu_id: i32,
     },
+    /// Any other event for a menu item.
+    ///
+    /// Hosts should send [`MenuEventType::Opened`] and [`MenuEventType::Closed`]
+    /// when showing and hiding a submenu, as some applications rely on them.
+    /// Using [`MenuEventType::Clicked`] is equivalent to [`ActivateRequest::MenuItem`].
+    MenuEvent {
+        address: String,
+        menu_path: String,
+        submenu_id: i32,
+        event_id: MenuEventType,
+    },
     /// Default activation for the tray.
     /// The parameter(x and y) represents screen coordinates and is to be considered an hint to the item where to show eventual windows (if any).
     Default { address: String, x: i32, y: i32 },
@@ -80,660 +259,2904 @@ pub enum ActivateRequest {
     Secondary { address: String, x: i32, y: i32 },
 }
 
-const PROPERTIES_INTERFACE: &str = "org.kde.StatusNotifierItem";
+impl ActivateRequest {
+    /// Creates a request to activate the menu item with id `submenu_id`
+    /// in the menu of `item`, which is registered at `address`.
+    ///
+    /// Returns `None` if the item has no menu.
+    #[must_use]
+    pub fn menu_item(address: &str, item: &StatusNotifierItem, submenu_id: i32) -> Option<Self> {
+        item.menu.as_ref().map(|menu_path| Self::MenuItem {
+            address: address.to_string(),
+            menu_path: menu_path.clone(),
+            submenu_id,
+        })
+    }
 
-/// Client for watching the tray.
-#[derive(Debug)]
-pub struct Client {
-    tx: broadcast::Sender<Event>,
-    _rx: broadcast::Receiver<Event>,
-    connection: Connection,
+    /// Creates a request for the default activation of the item at `address`,
+    /// with `x` and `y` being where the user clicked.
+    #[must_use]
+    pub fn default_activate(address: &str, x: i32, y: i32) -> Self {
+        Self::Default {
+            address: address.to_string(),
+            x,
+            y,
+        }
+    }
 
-    #[cfg(feature = "data")]
-    items: TrayItemMap,
+    /// Creates a request for the secondary activation of the item at `address`,
+    /// with `x` and `y` being where the user clicked.
+    #[must_use]
+    pub fn secondary_activate(address: &str, x: i32, y: i32) -> Self {
+        Self::Secondary {
+            address: address.to_string(),
+            x,
+            y,
+        }
+    }
 }
 
-impl Client {
-    /// Creates and initializes the client.
-    ///
-    /// The client will begin listening to items and menus and sending events immediately.
-    /// It is recommended that consumers immediately follow the call to `new` with a `subscribe` call,
-    /// then immediately follow that with a call to `items` to get the state to not miss any events.
-    ///
-    /// The value of `service_name` must be unique on the session bus.
-    /// It is recommended to use something similar to the format of `appid-numid`,
-    /// where `numid` is a short-ish random integer.
-    ///
-    /// # Errors
-    ///
-    /// If the initialization fails for any reason,
-    /// for example if unable to connect to the bus,
-    /// this method will return an error.
-    ///
-    /// # Panics
-    ///
-    /// If the generated well-known name is invalid, the library will panic
-    /// as this indicates a major bug.
-    ///
-    /// Likewise, the spawned tasks may panic if they cannot get a `Mutex` lock.
-    pub async fn new() -> Result<Self> {
-        let connection = Connection::session().await?;
-        let (tx, rx) = broadcast::channel(32);
+/// Whether the application handled an activate request,
+/// as returned by [`Client::activate_with_outcome`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ActivateOutcome {
+    /// The application handled the request.
+    Acknowledged,
+    /// The application did not reply within the request timeout.
+    TimedOut,
+    /// The application replied with an error,
+    /// for example because a disabled menu item was clicked.
+    Rejected(String),
+}
 
-        // first start server...
-        StatusNotifierWatcher::new().attach_to(&connection).await?;
+/// The coordinate space positions passed to [`ActivateRequest::Default`]
+/// and [`ActivateRequest::Secondary`] are given in.
+///
+/// The specification expects screen coordinates,
+/// but hosts on scaled outputs often only know the logical position of a click.
+/// Set the space using [`ClientBuilder::coordinate_space`]
+/// to have the client translate positions before sending them.
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum CoordinateSpace {
+    /// Positions are screen coordinates, and are sent unchanged.
+    #[default]
+    Screen,
+    /// Positions are logical coordinates,
+    /// which are multiplied by `scale` to get screen coordinates.
+    Logical { scale: f64 },
+}
 
-        // ...then connect to it
-        let watcher_proxy = StatusNotifierWatcherProxy::new(&connection).await?;
+impl CoordinateSpace {
+    /// Translates the position `x`, `y` in this space to screen coordinates,
+    /// rounding to the nearest pixel.
+    ///
+    /// Positions may be negative, such as on outputs left of or above the origin.
+    /// Returns `None` if the translated position cannot be represented as an `i32`.
+    #[must_use]
+    pub fn to_screen(self, x: i32, y: i32) -> Option<(i32, i32)> {
+        match self {
+            Self::Screen => Some((x, y)),
+            Self::Logical { scale } => Some((scale_position(x, scale)?, scale_position(y, scale)?)),
+        }
+    }
+}
 
-        // register a host on the watcher to declare we want to watch items
-        // get a well-known name
-        let pid = std::process::id();
-        let mut i = 0;
-        let wellknown = loop {
-            use zbus::fdo::RequestNameReply::{AlreadyOwner, Exists, InQueue, PrimaryOwner};
+#[allow(clippy::cast_possible_truncation)]
+fn scale_position(position: i32, scale: f64) -> Option<i32> {
+    let scaled = (f64::from(position) * scale).round();
+    (scaled >= f64::from(i32::MIN) && scaled <= f64::from(i32::MAX)).then_some(scaled as i32)
+}
 
-            i += 1;
-            let wellknown = format!("org.freedesktop.StatusNotifierHost-{pid}-{i}");
-            let wellknown: zbus::names::WellKnownName = wellknown
-                .try_into()
-                .expect("generated well-known name is invalid");
+/// The direction of a scroll on an item.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ScrollOrientation {
+    Vertical,
+    Horizontal,
+}
 
-            let flags = [zbus::fdo::RequestNameFlags::DoNotQueue];
-            match connection
-                .request_name_with_flags(&wellknown, flags.into_iter().collect())
-                .await?
-            {
-                PrimaryOwner => break wellknown,
-                Exists | AlreadyOwner => {}
-                InQueue => unreachable!(
-                    "request_name_with_flags returned InQueue even though we specified DoNotQueue"
-                ),
-            };
-        };
+impl ScrollOrientation {
+    /// Gets the orientation name sent over the bus.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Vertical => "vertical",
+            Self::Horizontal => "horizontal",
+        }
+    }
+}
 
-        debug!("wellknown: {wellknown}");
-        watcher_proxy
-            .register_status_notifier_host(&wellknown)
-            .await?;
-        let items = TrayItemMap::new();
+const PROPERTIES_INTERFACE: &str = "org.kde.StatusNotifierItem";
 
-        // handle new items
-        {
-            let connection = connection.clone();
-            let tx = tx.clone();
-            let items = items.clone();
+/// A predicate deciding which items the client emits events for.
+///
+/// Items which do not match are still tracked,
+/// and are re-checked whenever they are updated.
+/// When an item stops matching, a remove event is sent for it,
+/// and when it starts matching, an add event is sent.
+#[cfg(feature = "data")]
+#[derive(Clone)]
+pub struct EventFilter(Arc<dyn Fn(&StatusNotifierItem) -> bool + Send + Sync>);
 
-            let mut stream = watcher_proxy
-                .receive_status_notifier_item_registered()
-                .await?;
+#[cfg(feature = "data")]
+impl EventFilter {
+    /// Creates a filter which emits events for items matching `predicate`.
+    pub fn new(predicate: impl Fn(&StatusNotifierItem) -> bool + Send + Sync + 'static) -> Self {
+        Self(Arc::new(predicate))
+    }
 
-            spawn(async move {
-                while let Some(item) = stream.next().await {
-                    let address = item.args().map(|args| args.service);
-
-                    if let Ok(address) = address {
-                        debug!("received new item: {address}");
-                        if let Err(err) = Self::handle_item(
-                            address,
-                            connection.clone(),
-                            tx.clone(),
-                            items.clone(),
-                        )
-                        .await
-                        {
-                            error!("{err}");
-                            break;
-                        }
-                    }
-                }
+    /// Checks whether `item` matches the filter.
+    #[must_use]
+    pub fn matches(&self, item: &StatusNotifierItem) -> bool {
+        (self.0)(item)
+    }
 
-                Ok::<(), Error>(())
-            });
-        }
+    /// Creates a filter which matches items matching both this filter and `other`.
+    #[must_use]
+    pub fn and(self, other: Self) -> Self {
+        Self::new(move |item| self.matches(item) && other.matches(item))
+    }
+}
 
-        // then lastly get all items
-        // it can take so long to fetch all items that we have to do this last,
-        // otherwise some incoming items get missed
-        {
-            let connection = connection.clone();
-            let tx = tx.clone();
-            let items = items.clone();
+#[cfg(feature = "data")]
+impl std::fmt::Debug for EventFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EventFilter").finish_non_exhaustive()
+    }
+}
 
-            spawn(async move {
-                let initial_items = watcher_proxy.registered_status_notifier_items().await?;
-                debug!("initial items: {initial_items:?}");
+/// A menu being watched for an item.
+///
+/// The menu stops being watched once this is dropped,
+/// such as when the task watching the item is aborted.
+#[derive(Debug)]
+struct MenuWatch {
+    path: String,
+    task: Option<AbortHandle>,
+}
 
-                for item in initial_items {
-                    if let Err(err) =
-                        Self::handle_item(&item, connection.clone(), tx.clone(), items.clone())
-                            .await
-                    {
-                        error!("{err}");
-                    }
-                }
+impl MenuWatch {
+    /// Stops watching the menu.
+    fn abort(self) {
+        drop(self);
+    }
+}
 
-                Ok::<(), Error>(())
-            });
+impl Drop for MenuWatch {
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            task.abort();
         }
+    }
+}
 
-        // Handle other watchers unregistering and this one taking over
-        // It is necessary to clear all items as our watcher will then re-send them all
-        {
-            let tx = tx.clone();
-            let items = items.clone();
-
-            let dbus_proxy = DBusProxy::new(&connection).await?;
+/// The signals an item is watched through.
+struct ItemSignals {
+    /// The unique name which owns the item.
+    owner: String,
+    disconnect_stream: NameOwnerChangedStream,
+    props_changed: SignalStream<'static>,
+}
 
-            let mut stream = dbus_proxy.receive_name_acquired().await?;
+/// The default number of events buffered for each receiver.
+pub const DEFAULT_CAPACITY: usize = 32;
 
-            spawn(async move {
-                while let Some(thing) = stream.next().await {
-                    let body = thing.args()?;
-                    if body.name == names::WATCHER_BUS {
-                        for dest in items.clear_items() {
-                            tx.send(Event::Remove(dest))?;
-                        }
-                    }
-                }
+/// The default prefix of the well-known name the host is registered under.
+pub const DEFAULT_HOST_NAME_PREFIX: &str = "org.freedesktop.StatusNotifierHost";
 
-                Ok::<(), Error>(())
-            });
-        }
+/// The default number of times to retry fetching a new item's properties.
+pub const DEFAULT_ITEM_RETRIES: u32 = 2;
 
-        debug!("tray client initialized");
+/// How long to wait before the first retry of fetching an item's properties.
+/// This doubles after each attempt.
+const ITEM_RETRY_BACKOFF: Duration = Duration::from_millis(250);
 
-        Ok(Self {
-            connection,
-            tx,
-            _rx: rx,
-            #[cfg(feature = "data")]
-            items,
-        })
-    }
+/// How often to check whether receivers have caught up
+/// while updates are held back for them.
+const QUEUE_FLUSH_INTERVAL: Duration = Duration::from_millis(10);
 
-    /// Processes an incoming item to send the initial add event,
-    /// then set up listeners for it and its menu.
-    async fn handle_item(
-        address: &str,
-        connection: Connection,
-        tx: broadcast::Sender<Event>,
-        items: TrayItemMap,
-    ) -> crate::error::Result<()> {
-        let (destination, path) = parse_address(address);
+/// The default window for collapsing menu layout updates.
+pub const DEFAULT_LAYOUT_DEBOUNCE: Duration = Duration::from_millis(50);
 
-        let properties_proxy = PropertiesProxy::builder(&connection)
-            .destination(destination.to_string())?
-            .path(path.clone())?
-            .build()
-            .await?;
+/// The default number of menu levels fetched when watching a menu.
+///
+/// This is `-1`, meaning the entire menu is fetched however deeply it is nested.
+pub const DEFAULT_MENU_DEPTH: i32 = -1;
 
-        let properties = Self::get_item_properties(destination, &path, &properties_proxy).await?;
+/// The default time to wait for an item to respond to a request.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(1);
 
-        items.new_item(destination.into(), &properties);
+/// State shared between the client and its spawned tasks.
+#[derive(Debug, Clone)]
+#[allow(clippy::struct_excessive_bools)]
+struct Context {
+    request_timeout: Arc<Mutex<Duration>>,
+    menu_depth: i32,
+    embed_watcher: bool,
+    coalesce_updates: bool,
+    layout_debounce: Duration,
+    host_registration_events: bool,
+    item_retries: u32,
+    await_initial_items: bool,
+    #[cfg(feature = "data")]
+    readd_window: Option<Duration>,
+    host_name_prefix: String,
+    coordinate_space: CoordinateSpace,
+    prime_menus: bool,
+    /// The well-known name the host is registered under on the current connection.
+    host_name: Arc<Mutex<Option<OwnedWellKnownName>>>,
+    /// The task watching each item, by address.
+    watches: Arc<Mutex<HashMap<String, AbortHandle>>>,
+    tasks: Tasks,
+}
 
-        tx.send(Event::Add(
-            destination.to_string(),
-            properties.clone().into(),
-        ))?;
+impl Context {
+    fn request_timeout(&self) -> Duration {
+        *self.request_timeout.lock_or_recover()
+    }
 
-        {
-            let connection = connection.clone();
-            let destination = destination.to_string();
-            let items = items.clone();
-            let tx = tx.clone();
+    /// Translates an activation position to screen coordinates,
+    /// rejecting positions which cannot be represented once translated.
+    fn screen_position(&self, address: &str, x: i32, y: i32) -> Result<(i32, i32)> {
+        self.coordinate_space
+            .to_screen(x, y)
+            .ok_or_else(|| Error::InvalidCoordinates {
+                address: address.to_string(),
+                x,
+                y,
+            })
+    }
+}
 
-            spawn(async move {
-                Self::watch_item_properties(
-                    &destination,
-                    &path,
-                    &connection,
-                    properties_proxy,
-                    tx,
-                    items,
-                )
-                .await?;
+/// Tracks every task spawned by the client,
+/// so they can all be aborted when it shuts down.
+///
+/// The set is taken once the tasks have been aborted.
+#[derive(Debug, Clone)]
+struct Tasks {
+    set: Arc<Mutex<Option<JoinSet<()>>>>,
+    /// Where to send [`Event::Error`] when a task fails.
+    errors: broadcast::Sender<Event>,
+}
 
-                debug!("Stopped watching {destination}{path}");
-                Ok::<(), Error>(())
-            });
+impl Tasks {
+    fn new(errors: broadcast::Sender<Event>) -> Self {
+        Self {
+            set: Arc::new(Mutex::new(Some(JoinSet::new()))),
+            errors,
         }
+    }
 
-        if let Some(menu) = properties.menu {
-            let destination = destination.to_string();
+    /// Spawns `future` as a tracked task,
+    /// returning a handle to abort it individually.
+    ///
+    /// If the task fails, the error is logged and sent as an [`Event::Error`].
+    /// Once the tasks have been aborted, this does nothing.
+    fn spawn<F>(&self, future: F) -> Option<AbortHandle>
+    where
+        F: Future + Send + 'static,
+        F::Output: TaskOutput + Send + 'static,
+    {
+        let mut lock = self.set.lock_or_recover();
+        let tasks = lock.as_mut()?;
+
+        // reap finished tasks so the set does not grow with every item
+        while tasks.try_join_next().is_some() {}
+
+        let errors = self.errors.clone();
+        Some(tasks.spawn(async move {
+            match future.await.into_error() {
+                // nobody is listening to tell
+                None | Some(Error::EventSend(_)) => {}
+                Some(err) => {
+                    error!("background task failed: {err}");
+                    let _ = errors.send(Event::Error(err.to_string()));
+                }
+            }
+        }))
+    }
 
-            tx.send(Event::Update(
-                destination.clone(),
-                UpdateEvent::MenuConnect(menu.clone()),
-            ))?;
+    /// Aborts all tracked tasks,
+    /// and prevents any more from being spawned.
+    fn abort_all(&self) {
+        let tasks = self.set.lock_or_recover().take();
+        // dropping the set aborts its tasks
+        drop(tasks);
+    }
+}
 
-            spawn(async move {
-                Self::watch_menu(destination, &menu, &connection, tx, items).await?;
-                Ok::<(), Error>(())
-            });
-        }
+/// The output of a task spawned with [`Tasks::spawn`].
+trait TaskOutput {
+    /// Gets the error the task failed with, if any.
+    fn into_error(self) -> Option<Error>;
+}
 
-        Ok(())
+impl TaskOutput for () {
+    fn into_error(self) -> Option<Error> {
+        None
     }
+}
 
-    /// Gets the properties for an SNI item.
-    async fn get_item_properties(
-        destination: &str,
-        path: &str,
-        properties_proxy: &PropertiesProxy<'_>,
-    ) -> crate::error::Result<StatusNotifierItem> {
-        let properties = properties_proxy
-            .get_all(
-                InterfaceName::from_static_str(PROPERTIES_INTERFACE)
-                    .expect("to be valid interface name"),
-            )
-            .await;
+impl TaskOutput for Result<()> {
+    fn into_error(self) -> Option<Error> {
+        self.err()
+    }
+}
 
-        let properties = match properties {
-            Ok(properties) => properties,
-            Err(err) => {
-                error!("Error fetching properties from {destination}{path}: {err:?}");
-                return Err(err.into());
-            }
-        };
+/// Builder for a [`Client`] with non-default options.
+///
+/// Use [`Client::new`] if the defaults are fine.
+#[derive(Debug, Clone, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct ClientBuilder {
+    connection: Option<Connection>,
+    heartbeat_interval: Option<Duration>,
+    reconnect_backoff: Option<Duration>,
+    request_timeout: Option<Duration>,
+    menu_depth: Option<i32>,
+    external_watcher: bool,
+    capacity: Option<usize>,
+    coalesce_updates: bool,
+    layout_debounce: Option<Duration>,
+    host_registration_events: bool,
+    item_retries: Option<u32>,
+    await_initial_items: bool,
+    host_name_prefix: Option<String>,
+    coordinate_space: CoordinateSpace,
+    prime_menus: bool,
+    #[cfg(feature = "data")]
+    filter: Option<EventFilter>,
+    #[cfg(feature = "data")]
+    hide_passive: bool,
+    #[cfg(feature = "data")]
+    readd_window: Option<Duration>,
+}
 
-        StatusNotifierItem::try_from(DBusProps(properties))
+impl ClientBuilder {
+    /// Creates a new builder with the default options.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
     }
 
-    /// Watches an SNI item's properties,
-    /// sending an update event whenever they change.
-    async fn watch_item_properties(
+    /// Uses an existing connection instead of connecting to the session bus.
+    ///
+    /// See [`Client::with_connection`] for details.
+    #[must_use]
+    pub fn connection(mut self, connection: Connection) -> Self {
+        self.connection = Some(connection);
+        self
+    }
+
+    /// Sends an [`Event::Heartbeat`] every `interval`.
+    ///
+    /// Heartbeats are disabled by default.
+    #[must_use]
+    pub fn heartbeat_interval(mut self, interval: Duration) -> Self {
+        self.heartbeat_interval = Some(interval);
+        self
+    }
+
+    /// Automatically reconnects if the connection to the bus is lost,
+    /// retrying every `backoff` until it succeeds.
+    ///
+    /// Reconnecting always opens a new connection to the session bus,
+    /// even if the client was given an existing connection.
+    ///
+    /// See [`Client::new_with_reconnect`] for details.
+    /// Reconnection is disabled by default.
+    #[must_use]
+    pub fn reconnect(mut self, backoff: Duration) -> Self {
+        self.reconnect_backoff = Some(backoff);
+        self
+    }
+
+    /// Sets how long to wait for an item to respond to a request,
+    /// such as an activation or fetching its menu layout.
+    ///
+    /// Defaults to [`DEFAULT_REQUEST_TIMEOUT`].
+    /// This can be changed later using [`Client::set_request_timeout`].
+    #[must_use]
+    pub fn request_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    /// Sets how many levels of each menu are fetched when watching it.
+    ///
+    /// Submenus below this depth are left empty,
+    /// and can be fetched when needed using [`Client::get_submenu`].
+    /// A depth of `-1` fetches the entire menu.
+    /// Limiting the depth can help with very large menus,
+    /// at the cost of fetching submenus separately.
+    ///
+    /// Defaults to [`DEFAULT_MENU_DEPTH`], the entire menu.
+    #[must_use]
+    pub fn max_menu_depth(mut self, depth: i32) -> Self {
+        self.menu_depth = Some(depth);
+        self
+    }
+
+    /// Sets how many events are buffered for each receiver.
+    ///
+    /// The channel is bounded, so memory use does not grow
+    /// if a receiver stops reading.
+    ///
+    /// Once a receiver falls this many events behind,
+    /// update events are held back until it catches up,
+    /// and consecutive updates of the same kind for the same item
+    /// are coalesced into the latest, such as the latest icon or status.
+    /// Updates to different extension properties are kept apart.
+    ///
+    /// Menu diffs and subtrees are never coalesced, as each builds on the last,
+    /// and neither are events other than updates.
+    /// These are sent straight away, after any held back updates,
+    /// so if a receiver is still full the oldest events are dropped
+    /// and it gets a [`broadcast::error::RecvError::Lagged`] error with the number dropped.
+    /// A lagged receiver should resync
+    /// from `Client::items` or `Client::subscribe_with_snapshot`.
+    ///
+    /// Defaults to [`DEFAULT_CAPACITY`].
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    #[must_use]
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        assert!(capacity > 0, "capacity must be greater than zero");
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Sets whether to coalesce bursts of item signals.
+    ///
+    /// When enabled, signals which have already arrived by the time one is handled
+    /// are handled together, and the property for each kind of signal is only fetched once.
+    /// This cuts down on bus traffic for items which signal changes many times a second,
+    /// but means only one update event is sent for each burst.
+    ///
+    /// Disabled by default.
+    #[must_use]
+    pub fn coalesce_updates(mut self, coalesce: bool) -> Self {
+        self.coalesce_updates = coalesce;
+        self
+    }
+
+    /// Sets whether to send [`Event::HostRegistrationChanged`]
+    /// when the watcher gains its first host or loses its last.
+    ///
+    /// Disabled by default.
+    #[must_use]
+    pub fn host_registration_events(mut self, enabled: bool) -> Self {
+        self.host_registration_events = enabled;
+        self
+    }
+
+    /// Sets how many times to retry fetching a new item's properties.
+    ///
+    /// Some applications register an item before they are ready to serve it.
+    /// Each attempt is limited by the [request timeout](Self::request_timeout),
+    /// and retries back off, starting at a quarter of a second.
+    /// If every attempt fails but the item is still on the bus,
+    /// it is added as a placeholder with only its address as the id
+    /// and a status of [`Status::Unknown`](crate::item::Status::Unknown),
+    /// so that the host can still show something for it.
+    /// Each item is loaded separately, so retries do not hold up other items.
+    ///
+    /// This is also how many times watching an item or its menu is restarted
+    /// after failing, for example because of a transient bus error.
+    /// Once those restarts are used up, the item is removed,
+    /// or its menu is disconnected.
+    ///
+    /// Defaults to [`DEFAULT_ITEM_RETRIES`].
+    #[must_use]
+    pub fn item_retries(mut self, retries: u32) -> Self {
+        self.item_retries = Some(retries);
+        self
+    }
+
+    /// Sets whether building the client waits for the items
+    /// which are already registered to be loaded.
+    ///
+    /// When enabled, [`Client::items`] includes every initial item
+    /// which responded straight away as soon as the client is returned,
+    /// and [`Event::Ready`] has already been sent.
+    /// Items which register while the initial ones are loading are handled afterwards,
+    /// so none are missed.
+    ///
+    /// See [`Client::new_awaiting_initial`] for details.
+    /// Disabled by default.
+    #[must_use]
+    pub fn await_initial_items(mut self, wait: bool) -> Self {
+        self.await_initial_items = wait;
+        self
+    }
+
+    /// Sets whether to send `AboutToShow` for the root of each menu
+    /// before fetching its layout.
+    ///
+    /// Some applications, notably those using libdbusmenu,
+    /// only populate their menu once it is about to be shown,
+    /// so the layout fetched when the item is added is empty or stale.
+    /// Priming the menu gets them to populate it first.
+    ///
+    /// Disabled by default.
+    #[must_use]
+    pub fn prime_menus(mut self, prime: bool) -> Self {
+        self.prime_menus = prime;
+        self
+    }
+
+    /// Sets the prefix of the well-known name the host is registered under.
+    ///
+    /// The process id and a counter are still appended for uniqueness,
+    /// giving a name in the format `{prefix}-{pid}-{i}`.
+    /// This is useful in sandboxes which restrict the names that can be owned,
+    /// or for picking out a particular host in tests.
+    ///
+    /// Defaults to [`DEFAULT_HOST_NAME_PREFIX`].
+    #[must_use]
+    pub fn host_name_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.host_name_prefix = Some(prefix.into());
+        self
+    }
+
+    /// Sets the coordinate space of positions passed to activation requests.
+    ///
+    /// Positions are translated to screen coordinates before being sent,
+    /// so hosts which only know logical positions can pass them as they are.
+    ///
+    /// Defaults to [`CoordinateSpace::Screen`], which sends positions unchanged.
+    #[must_use]
+    pub fn coordinate_space(mut self, space: CoordinateSpace) -> Self {
+        self.coordinate_space = space;
+        self
+    }
+
+    /// Sets how long to wait for further menu layout updates before fetching the layout.
+    ///
+    /// Some applications signal layout updates many times in quick succession.
+    /// Any updates received within `window` of the first are collapsed,
+    /// so the layout is only fetched once.
+    /// A zero window fetches the layout immediately on every update.
+    ///
+    /// Defaults to [`DEFAULT_LAYOUT_DEBOUNCE`].
+    #[must_use]
+    pub fn layout_debounce(mut self, window: Duration) -> Self {
+        self.layout_debounce = Some(window);
+        self
+    }
+
+    /// Sets whether to run a `StatusNotifierWatcher` within the client.
+    ///
+    /// The embedded watcher is only used if no other watcher owns the name on the bus,
+    /// and takes over if the other watcher goes away.
+    /// When disabled, the client relies on an existing watcher,
+    /// such as the one provided by KDE Plasma,
+    /// and fails to start if there is none.
+    ///
+    /// Enabled by default.
+    #[must_use]
+    pub fn embed_watcher(mut self, embed: bool) -> Self {
+        self.external_watcher = !embed;
+        self
+    }
+
+    /// Only emits events for items matching `filter`.
+    ///
+    /// Items which do not match are also left out of [`Client::items`].
+    /// See [`EventFilter`] for details.
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn filter(mut self, filter: EventFilter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Keeps items for up to `window` when the embedded watcher takes over,
+    /// instead of removing them straight away.
+    ///
+    /// Items re-register with the watcher when it changes hands.
+    /// By default, a remove event is sent for every item when this happens,
+    /// followed by an add event as each one re-registers.
+    /// With this set, an item which re-registers within `window`
+    /// with the same id, category and title is kept,
+    /// with an update event sent for each property which changed in the meantime.
+    /// Only the items which do not re-register are removed once the window ends.
+    ///
+    /// Disabled by default.
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn coalesce_readded_items(mut self, window: Duration) -> Self {
+        self.readd_window = Some(window);
+        self
+    }
+
+    /// Sets whether to hide items with a [`Status::Passive`](crate::item::Status::Passive) status.
+    ///
+    /// Passive items are still tracked, so when one becomes active
+    /// an add event is sent for it, and a remove event when it becomes passive again.
+    /// This is combined with any [filter](Self::filter).
+    ///
+    /// Disabled by default.
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn hide_passive(mut self, hide: bool) -> Self {
+        self.hide_passive = hide;
+        self
+    }
+
+    /// Creates and initializes the client using the configured options.
+    ///
+    /// See [`Client::new`] for details.
+    ///
+    /// # Errors
+    ///
+    /// If the initialization fails for any reason,
+    /// for example if unable to connect to the bus
+    /// or the host name prefix does not make a valid bus name,
+    /// this method will return an error.
+    pub async fn build(self) -> Result<Client> {
+        Client::with_builder(self).await
+    }
+}
+
+/// Client for watching the tray.
+///
+/// Dropping the client stops its background tasks,
+/// after which subscribers receive no further events.
+/// Use [`Client::shutdown`] to also release the host name immediately.
+#[derive(Debug)]
+pub struct Client {
+    tx: broadcast::Sender<Event>,
+    envelope_tx: broadcast::Sender<EventEnvelope>,
+    connection: Arc<Mutex<Connection>>,
+    ctx: Context,
+    /// Categories fetched by [`Client::registered_items_by_category`],
+    /// which never change for an item.
+    categories: Mutex<HashMap<String, Category>>,
+
+    /// Also keeps the events channel open,
+    /// so sending never fails for lack of receivers.
+    items: TrayItemMap,
+}
+
+impl Client {
+    /// Creates and initializes the client.
+    ///
+    /// The client will begin listening to items and menus and sending events immediately.
+    /// It is recommended that consumers immediately follow the call to `new` with a `subscribe` call,
+    /// then immediately follow that with a call to `items` to get the state to not miss any events.
+    /// Alternatively, `subscribe_with_snapshot` does both atomically,
+    /// or [`Client::new_awaiting_initial`] waits for the initial items to be in the state.
+    ///
+    /// The value of `service_name` must be unique on the session bus.
+    /// It is recommended to use something similar to the format of `appid-numid`,
+    /// where `numid` is a short-ish random integer.
+    ///
+    /// # Errors
+    ///
+    /// If the initialization fails for any reason,
+    /// for example if unable to connect to the bus,
+    /// this method will return an error.
+    pub async fn new() -> Result<Self> {
+        Self::builder().build().await
+    }
+
+    /// Creates a builder for configuring the client before initializing it.
+    #[must_use]
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
+    /// Creates and initializes a client which automatically reconnects
+    /// if the connection to the bus is lost, for example if the bus daemon restarts.
+    ///
+    /// After losing the connection, a remove event is sent for every item,
+    /// then reconnection is attempted every `backoff` until it succeeds.
+    /// Items are re-added as they are rediscovered.
+    ///
+    /// # Errors
+    ///
+    /// If the initial connection fails for any reason, this method will return an error.
+    pub async fn new_with_reconnect(backoff: Duration) -> Result<Self> {
+        Self::builder().reconnect(backoff).build().await
+    }
+
+    /// Creates and initializes a client,
+    /// waiting for the items which are already registered to be loaded before returning.
+    ///
+    /// This avoids having to subscribe before calling `items` to catch the initial items,
+    /// as they are all in the state by the time the client is returned.
+    /// Loading each item is limited by the request timeout,
+    /// so this can take a while if many items are slow to respond.
+    ///
+    /// # Errors
+    ///
+    /// If the initialization fails for any reason,
+    /// including failing to list the registered items,
+    /// this method will return an error.
+    pub async fn new_awaiting_initial() -> Result<Self> {
+        Self::builder().await_initial_items(true).build().await
+    }
+
+    /// Creates and initializes a client using an existing connection,
+    /// for example to a private bus or the system bus.
+    ///
+    /// See [`Client::new`] for details.
+    ///
+    /// # Errors
+    ///
+    /// If the initialization fails for any reason,
+    /// this method will return an error.
+    pub async fn with_connection(connection: Connection) -> Result<Self> {
+        Self::builder().connection(connection).build().await
+    }
+
+    /// Creates and initializes a client which only emits events
+    /// for items matching `filter`.
+    ///
+    /// See [`EventFilter`] for details.
+    ///
+    /// # Errors
+    ///
+    /// If the initialization fails for any reason,
+    /// this method will return an error.
+    #[cfg(feature = "data")]
+    pub async fn new_with_filter(filter: EventFilter) -> Result<Self> {
+        Self::builder().filter(filter).build().await
+    }
+
+    async fn with_builder(mut builder: ClientBuilder) -> Result<Self> {
+        let host_name_prefix = builder
+            .host_name_prefix
+            .take()
+            .unwrap_or_else(|| DEFAULT_HOST_NAME_PREFIX.to_string());
+
+        // check up front so a bad prefix fails before anything is started
+        zbus::names::WellKnownName::try_from(format!("{host_name_prefix}-0-0"))
+            .map_err(|_| Error::InvalidData("host name prefix is not a valid bus name"))?;
+
+        let connection = match builder.connection.take() {
+            Some(connection) => connection,
+            None => Connection::session().await?,
+        };
+        let capacity = builder.capacity.unwrap_or(DEFAULT_CAPACITY);
+        let (tx, rx) = broadcast::channel(capacity);
+        let queue = Arc::new(EventQueue::new(capacity, rx));
+
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "data")] {
+                let mut filter = builder.filter.take();
+                if builder.hide_passive {
+                    let not_passive =
+                        EventFilter::new(|item| item.status != crate::item::Status::Passive);
+                    filter = Some(match filter {
+                        Some(filter) => filter.and(not_passive),
+                        None => not_passive,
+                    });
+                }
+
+                let items = TrayItemMap::with_filter(filter).with_queue(queue.clone());
+            } else {
+                let items = TrayItemMap::new().with_queue(queue.clone());
+            }
+        }
+        let ctx = Context {
+            request_timeout: Arc::new(Mutex::new(
+                builder.request_timeout.unwrap_or(DEFAULT_REQUEST_TIMEOUT),
+            )),
+            menu_depth: builder.menu_depth.unwrap_or(DEFAULT_MENU_DEPTH),
+            embed_watcher: !builder.external_watcher,
+            coalesce_updates: builder.coalesce_updates,
+            host_registration_events: builder.host_registration_events,
+            item_retries: builder.item_retries.unwrap_or(DEFAULT_ITEM_RETRIES),
+            await_initial_items: builder.await_initial_items,
+            #[cfg(feature = "data")]
+            readd_window: builder.readd_window,
+            layout_debounce: builder.layout_debounce.unwrap_or(DEFAULT_LAYOUT_DEBOUNCE),
+            host_name_prefix,
+            coordinate_space: builder.coordinate_space,
+            prime_menus: builder.prime_menus,
+            host_name: Arc::default(),
+            watches: Arc::default(),
+            tasks: Tasks::new(tx.clone()),
+        };
+
+        let registered = Self::attach(&connection, &tx, &items, &ctx).await?;
+        let shared_connection = Arc::new(Mutex::new(connection.clone()));
+
+        // handle new items,
+        // reconnecting once the stream ends if enabled
+        {
+            let shared_connection = shared_connection.clone();
+            let tx = tx.clone();
+            let items = items.clone();
+            let tasks = ctx.tasks.clone();
+            let ctx = ctx.clone();
+
+            tasks.spawn(async move {
+                let mut connection = connection;
+                let mut registered = registered;
+
+                loop {
+                    Self::watch_registered_items(registered, &connection, &tx, &items, &ctx).await;
+
+                    // the items can no longer be watched,
+                    // so consumers should not be left showing them
+                    items.clear(&tx)?;
+
+                    let Some(backoff) = builder.reconnect_backoff else {
+                        break;
+                    };
+
+                    warn!("lost connection to the bus, reconnecting");
+
+                    (connection, registered) = Self::reconnect(backoff, &tx, &items, &ctx).await;
+                    *shared_connection.lock_or_recover() = connection.clone();
+                }
+
+                Ok::<(), Error>(())
+            });
+        }
+
+        if let Some(interval) = builder.heartbeat_interval {
+            ctx.tasks.spawn(send_heartbeats(tx.clone(), interval));
+        }
+
+        ctx.tasks
+            .spawn(flush_queued_events(queue.clone(), tx.clone()));
+
+        let (envelope_tx, _) = broadcast::channel(capacity);
+        ctx.tasks
+            .spawn(sequence_events(tx.subscribe(), envelope_tx.clone()));
+
+        debug!("tray client initialized");
+
+        Ok(Self {
+            connection: shared_connection,
+            ctx,
+            categories: Mutex::default(),
+            tx,
+            envelope_tx,
+            items,
+        })
+    }
+
+    /// Starts the watcher and registers a host on `connection`,
+    /// then starts fetching the items which are already registered.
+    ///
+    /// Returns the stream of newly registered items.
+    async fn attach(
+        connection: &Connection,
+        tx: &broadcast::Sender<Event>,
+        items: &TrayItemMap,
+        ctx: &Context,
+    ) -> Result<StatusNotifierItemRegisteredStream> {
+        // first start server if enabled...
+        if ctx.embed_watcher {
+            StatusNotifierWatcher::new().attach_to(connection).await?;
+        }
+
+        // ...then connect to it
+        let watcher_proxy = StatusNotifierWatcherProxy::new(connection).await?;
+
+        // register a host on the watcher to declare we want to watch items
+        // get a well-known name
+        let pid = std::process::id();
+        let mut i = 0;
+        let wellknown = loop {
+            use zbus::fdo::RequestNameReply::{AlreadyOwner, Exists, InQueue, PrimaryOwner};
+
+            i += 1;
+            let wellknown = format!("{}-{pid}-{i}", ctx.host_name_prefix);
+            let wellknown: zbus::names::WellKnownName = wellknown
+                .try_into()
+                .map_err(|_| Error::InvalidData("generated well-known name is invalid"))?;
+
+            let flags = [zbus::fdo::RequestNameFlags::DoNotQueue];
+            match connection
+                .request_name_with_flags(&wellknown, flags.into_iter().collect())
+                .await
+            {
+                Ok(PrimaryOwner) => break wellknown,
+                // zbus reports `Exists` as an error
+                Ok(Exists | AlreadyOwner) | Err(zbus::Error::NameTaken) => {}
+                Ok(InQueue) => unreachable!(
+                    "request_name_with_flags returned InQueue even though we specified DoNotQueue"
+                ),
+                Err(err) => return Err(err.into()),
+            };
+        };
+
+        debug!("wellknown: {wellknown}");
+        watcher_proxy
+            .register_status_notifier_host(&wellknown)
+            .await?;
+
+        *ctx.host_name.lock_or_recover() = Some(wellknown.into());
+
+        if ctx.host_registration_events {
+            // the watcher is not guaranteed to signal property changes,
+            // so the property is always fetched fresh
+            let watcher_proxy = StatusNotifierWatcherProxy::builder(connection)
+                .cache_properties(CacheProperties::No)
+                .build()
+                .await?;
+
+            let mut host_registered = watcher_proxy
+                .receive_status_notifier_host_registered()
+                .await?;
+            let mut host_unregistered = watcher_proxy
+                .receive_status_notifier_host_unregistered()
+                .await?;
+
+            let tx = tx.clone();
+            let items = items.clone();
+
+            ctx.tasks.spawn(async move {
+                let mut registered = watcher_proxy.is_status_notifier_host_registered().await?;
+
+                loop {
+                    tokio::select! {
+                        Some(_) = host_registered.next() => {}
+                        Some(_) = host_unregistered.next() => {}
+                        else => break,
+                    }
+
+                    let is_registered = watcher_proxy.is_status_notifier_host_registered().await?;
+                    if is_registered != registered {
+                        registered = is_registered;
+                        items.send(&tx, Event::HostRegistrationChanged(registered))?;
+                    }
+                }
+
+                Ok::<(), Error>(())
+            });
+        }
+
+        // subscribe to new items before fetching the existing ones
+        let registered = watcher_proxy
+            .receive_status_notifier_item_registered()
+            .await?;
+
+        // then lastly get all items
+        // it can take so long to fetch all items that we have to do this last,
+        // otherwise some incoming items get missed
+        let initial_items = {
+            let connection = connection.clone();
+            let tx = tx.clone();
+            let items = items.clone();
+            let ctx = ctx.clone();
+
+            async move {
+                let initial_items = watcher_proxy.registered_status_notifier_items().await?;
+                debug!("initial items: {initial_items:?}");
+
+                // items are loaded in parallel,
+                // so an unresponsive one only holds up `Ready` until its first attempt fails
+                let settled = initial_items
+                    .into_iter()
+                    .map(|item| {
+                        let (settled, settled_rx) = oneshot::channel::<()>();
+                        Self::spawn_item(item, true, Some(settled), &connection, &tx, &items, &ctx);
+                        settled_rx
+                    })
+                    .collect::<Vec<_>>();
+
+                for settled in settled {
+                    // the sender is dropped rather than sent on
+                    let _ = settled.await;
+                }
+
+                items.send(&tx, Event::Ready)?;
+
+                Ok::<(), Error>(())
+            }
+        };
+
+        // Handle other watchers unregistering and this one taking over
+        // It is necessary to clear all items as our watcher will then re-send them all
+        if ctx.embed_watcher {
+            let tx = tx.clone();
+            let items = items.clone();
+            #[cfg(feature = "data")]
+            let ctx = ctx.clone();
+
+            let dbus_proxy = DBusProxy::new(connection).await?;
+
+            let mut stream = dbus_proxy.receive_name_acquired().await?;
+
+            ctx.tasks.clone().spawn(async move {
+                while let Some(thing) = stream.next().await {
+                    let body = thing.args()?;
+                    if body.name != names::WATCHER_BUS {
+                        continue;
+                    }
+
+                    #[cfg(feature = "data")]
+                    if let Some(window) = ctx.readd_window {
+                        items.expect_readd();
+
+                        let tx = tx.clone();
+                        let items = items.clone();
+                        ctx.tasks.spawn(async move {
+                            tokio::time::sleep(window).await;
+                            items.remove_unreadded(&tx)
+                        });
+
+                        continue;
+                    }
+
+                    items.clear(&tx)?;
+                }
+
+                Ok::<(), Error>(())
+            });
+        }
+
+        // items registering in the meantime queue up on the registered stream
+        if ctx.await_initial_items {
+            initial_items.await?;
+        } else {
+            ctx.tasks.spawn(initial_items);
+        }
+
+        Ok(registered)
+    }
+
+    /// Repeatedly attempts to connect to the session bus and attach to it,
+    /// waiting `backoff` before each attempt.
+    async fn reconnect(
+        backoff: Duration,
+        tx: &broadcast::Sender<Event>,
+        items: &TrayItemMap,
+        ctx: &Context,
+    ) -> (Connection, StatusNotifierItemRegisteredStream) {
+        loop {
+            tokio::time::sleep(backoff).await;
+
+            let attempt = async {
+                let connection = Connection::session().await?;
+                let registered = Self::attach(&connection, tx, items, ctx).await?;
+                Ok::<_, Error>((connection, registered))
+            };
+
+            match attempt.await {
+                Ok(res) => {
+                    debug!("reconnected to the bus");
+                    break res;
+                }
+                Err(err) => error!("failed to reconnect: {err}"),
+            }
+        }
+    }
+
+    /// Handles newly registered items until the stream ends,
+    /// which happens when the connection is lost.
+    async fn watch_registered_items(
+        mut stream: StatusNotifierItemRegisteredStream,
+        connection: &Connection,
+        tx: &broadcast::Sender<Event>,
+        items: &TrayItemMap,
+        ctx: &Context,
+    ) {
+        while let Some(item) = stream.next().await {
+            let address = item.args().map(|args| args.service);
+
+            if let Ok(address) = address {
+                debug!("received new item: {address}");
+                Self::spawn_item(address.to_string(), false, None, connection, tx, items, ctx);
+            }
+        }
+    }
+
+    /// Spawns a task to handle an incoming item,
+    /// so that an unresponsive item does not hold up any others.
+    ///
+    /// See [`Client::handle_item`].
+    fn spawn_item(
+        address: String,
+        initial: bool,
+        settled: Option<oneshot::Sender<()>>,
+        connection: &Connection,
+        tx: &broadcast::Sender<Event>,
+        items: &TrayItemMap,
+        ctx: &Context,
+    ) {
+        let connection = connection.clone();
+        let tx = tx.clone();
+        let items = items.clone();
+        let tasks = ctx.tasks.clone();
+        let ctx = ctx.clone();
+
+        tasks.spawn(async move {
+            if let Err(err) =
+                Self::handle_item(&address, initial, settled, connection, tx, items, ctx).await
+            {
+                error!("{err}");
+            }
+        });
+    }
+
+    /// Processes an incoming item to send the initial add event,
+    /// then set up listeners for it and its menu.
+    ///
+    /// `initial` is whether the item was registered before the client attached.
+    /// `settled` is dropped once the item has been added,
+    /// or once the first attempt to fetch its properties has failed.
+    async fn handle_item(
+        address: &str,
+        initial: bool,
+        mut settled: Option<oneshot::Sender<()>>,
+        connection: Connection,
+        tx: broadcast::Sender<Event>,
+        items: TrayItemMap,
+        ctx: Context,
+    ) -> crate::error::Result<()> {
+        let (destination, path) = parse_address(address);
+
+        let properties_proxy = PropertiesProxy::builder(&connection)
+            .destination(destination.to_string())?
+            .path(path.clone())?
+            .build()
+            .await?;
+
+        let properties = Self::get_item_properties_with_retries(
+            destination,
+            &path,
+            &properties_proxy,
+            &mut settled,
+            &ctx,
+        )
+        .await;
+
+        let properties = match properties {
+            Ok(properties) => properties,
+            Err(err) if is_malformed(&err) => {
+                warn!("[{destination}{path}] ignoring malformed item: {err}");
+                items.send(
+                    &tx,
+                    Event::Malformed {
+                        address: destination.to_string(),
+                        reason: err.to_string(),
+                    },
+                )?;
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+
+        // subscribe before the item is announced,
+        // so that no changes made once it is seen are missed
+        let signals = Self::subscribe_item(destination, &path, &connection).await?;
+
+        // the same item can be seen both in the initial items
+        // and as a newly registered item if it registers during startup,
+        // and items re-added unchanged after the watcher is taken over are still watched
+        if !items.send(
+            &tx,
+            Event::Add {
+                address: destination.to_string(),
+                item: properties.clone().into(),
+                initial,
+            },
+        )? {
+            debug!("ignoring duplicate item: {address}");
+            return Ok(());
+        }
+
+        // a different item re-added after the watcher is taken over
+        // replaces the old one, along with its menu
+        if let Some(old) = ctx.watches.lock_or_recover().remove(destination) {
+            debug!("[{destination}{path}] replacing watched item");
+            old.abort();
+        }
+
+        let menu = match properties.menu {
+            Some(menu) => Some(Self::connect_menu(
+                destination,
+                &menu,
+                &connection,
+                &tx,
+                &items,
+                &ctx,
+            )?),
+            None => None,
+        };
+
+        let destination = destination.to_string();
+        let tasks = ctx.tasks.clone();
+        let watches = ctx.watches.clone();
+        let key = destination.clone();
+
+        let watch = tasks.spawn(async move {
+            let mut item_is_menu = properties.item_is_menu;
+            let mut menu = menu;
+            let mut signals = Some(signals);
+
+            let mut backoff = ITEM_RETRY_BACKOFF;
+            let mut attempt = 0;
+
+            loop {
+                let res = Self::watch_item_properties(
+                    &destination,
+                    &path,
+                    &connection,
+                    signals.take(),
+                    &properties_proxy,
+                    &mut item_is_menu,
+                    &mut menu,
+                    &tx,
+                    &items,
+                    &ctx,
+                )
+                .await;
+
+                match res {
+                    Ok(()) => break,
+                    Err(err) if attempt < ctx.item_retries => {
+                        attempt += 1;
+                        warn!("[{destination}{path}] restarting watch in {backoff:?} (attempt {attempt}): {err}");
+
+                        tokio::time::sleep(backoff).await;
+                        backoff *= 2;
+                    }
+                    Err(err) => {
+                        error!("[{destination}{path}] removing item after watch failed: {err}");
+
+                        if let Some(menu) = menu.take() {
+                            menu.abort();
+                        }
+
+                        items.send(&tx, Event::Remove(destination.clone()))?;
+                        break;
+                    }
+                }
+            }
+
+            debug!("Stopped watching {destination}{path}");
+
+            let mut watches = ctx.watches.lock_or_recover();
+            if watches
+                .get(&destination)
+                .is_some_and(|watch| watch.id() == tokio::task::id())
+            {
+                watches.remove(&destination);
+            }
+
+            Ok::<(), Error>(())
+        });
+
+        if let Some(watch) = watch {
+            watches.lock_or_recover().insert(key, watch);
+        }
+
+        Ok(())
+    }
+
+    /// Sends a menu connect event for the menu at `menu_path`,
+    /// then starts watching it.
+    fn connect_menu(
+        destination: &str,
+        menu_path: &str,
+        connection: &Connection,
+        tx: &broadcast::Sender<Event>,
+        items: &TrayItemMap,
+        ctx: &Context,
+    ) -> Result<MenuWatch> {
+        items.send(
+            tx,
+            Event::Update(
+                destination.to_string(),
+                UpdateEvent::MenuConnect(menu_path.to_string()),
+            ),
+        )?;
+
+        let task = {
+            let destination = destination.to_string();
+            let menu_path = menu_path.to_string();
+            let connection = connection.clone();
+            let tx = tx.clone();
+            let items = items.clone();
+            let ctx = ctx.clone();
+
+            let tasks = ctx.tasks.clone();
+            tasks.spawn(async move {
+                let mut backoff = ITEM_RETRY_BACKOFF;
+                let mut attempt = 0;
+
+                loop {
+                    let res = Self::watch_menu(
+                        destination.clone(),
+                        &menu_path,
+                        &connection,
+                        tx.clone(),
+                        items.clone(),
+                        ctx.clone(),
+                    )
+                    .await;
+
+                    match res {
+                        Ok(()) => break,
+                        Err(err) if attempt < ctx.item_retries => {
+                            attempt += 1;
+                            warn!("[{destination}{menu_path}] restarting menu watch in {backoff:?} (attempt {attempt}): {err}");
+
+                            tokio::time::sleep(backoff).await;
+                            backoff *= 2;
+                        }
+                        Err(err) => {
+                            error!("[{destination}{menu_path}] disconnecting menu after watch failed: {err}");
+                            items.send(
+                                &tx,
+                                Event::Update(destination.clone(), UpdateEvent::MenuDisconnect),
+                            )?;
+                            break;
+                        }
+                    }
+                }
+
+                Ok::<(), Error>(())
+            })
+        };
+
+        Ok(MenuWatch {
+            path: menu_path.to_string(),
+            task,
+        })
+    }
+
+    /// Gets the properties for an SNI item,
+    /// retrying failures and timeouts up to the configured number of times.
+    ///
+    /// If every attempt fails but the item is still on the bus,
+    /// a placeholder item is returned.
+    /// `settled` is dropped once the first attempt fails.
+    async fn get_item_properties_with_retries(
+        destination: &str,
+        path: &str,
+        properties_proxy: &PropertiesProxy<'_>,
+        settled: &mut Option<oneshot::Sender<()>>,
+        ctx: &Context,
+    ) -> crate::error::Result<StatusNotifierItem> {
+        let mut backoff = ITEM_RETRY_BACKOFF;
+        let mut attempt = 0;
+
+        loop {
+            let properties = timeout(
+                ctx.request_timeout(),
+                Self::get_item_properties(destination, path, properties_proxy),
+            )
+            .await
+            .unwrap_or_else(|_| {
+                Err(Error::ItemProperties {
+                    destination: destination.to_string(),
+                    source: zbus::fdo::Error::TimedOut("timed out fetching properties".to_string()),
+                })
+            });
+
+            if properties.is_err() {
+                settled.take();
+            }
+
+            match properties {
+                Ok(properties) => break Ok(properties),
+                Err(
+                    err @ Error::ItemProperties {
+                        source:
+                            zbus::fdo::Error::ServiceUnknown(_) | zbus::fdo::Error::NameHasNoOwner(_),
+                        ..
+                    },
+                ) => break Err(err),
+                // the item responded, so retrying will not help
+                Err(err) if is_malformed(&err) => break Err(err),
+                Err(err) if attempt < ctx.item_retries => {
+                    attempt += 1;
+                    warn!(
+                        "[{destination}{path}] retrying in {backoff:?} (attempt {attempt}): {err}"
+                    );
+
+                    tokio::time::sleep(backoff).await;
+                    backoff *= 2;
+                }
+                Err(err) => {
+                    warn!("[{destination}{path}] adding placeholder item: {err}");
+                    break Ok(StatusNotifierItem::placeholder(destination.to_string()));
+                }
+            }
+        }
+    }
+
+    /// Gets the properties for an SNI item.
+    async fn get_item_properties(
+        destination: &str,
+        path: &str,
+        properties_proxy: &PropertiesProxy<'_>,
+    ) -> crate::error::Result<StatusNotifierItem> {
+        let properties = properties_proxy
+            .get_all(
+                InterfaceName::from_static_str(PROPERTIES_INTERFACE)
+                    .expect("to be valid interface name"),
+            )
+            .await;
+
+        let properties = match properties {
+            Ok(properties) => properties,
+            Err(err) => {
+                error!("Error fetching properties from {destination}{path}: {err:?}");
+                return Err(Error::ItemProperties {
+                    destination: destination.to_string(),
+                    source: err,
+                });
+            }
+        };
+
+        StatusNotifierItem::try_from(DBusProps(properties))
+    }
+
+    /// Watches an SNI item's properties,
+    /// sending an update event whenever they change.
+    ///
+    /// `ItemIsMenu` and `Menu` have no standard change signals,
+    /// so they are polled whenever any signal is received.
+    /// If the menu path changes, the old menu stops being watched
+    /// and the new one is connected in its place.
+    ///
+    /// The item is subscribed to again if `signals` is `None`.
+    #[allow(clippy::too_many_arguments)]
+    async fn watch_item_properties(
+        destination: &str,
+        path: &str,
+        connection: &Connection,
+        signals: Option<ItemSignals>,
+        properties_proxy: &PropertiesProxy<'_>,
+        item_is_menu: &mut bool,
+        menu: &mut Option<MenuWatch>,
+        tx: &broadcast::Sender<Event>,
+        items: &TrayItemMap,
+        ctx: &Context,
+    ) -> crate::error::Result<()> {
+        let ItemSignals {
+            owner,
+            mut disconnect_stream,
+            mut props_changed,
+        } = match signals {
+            Some(signals) => signals,
+            None => Self::subscribe_item(destination, path, connection).await?,
+        };
+
+        loop {
+            tokio::select! {
+                Some(change) = props_changed.next() => {
+                    let mut changes = vec![change];
+
+                    if ctx.coalesce_updates {
+                        while let Some(Some(change)) = poll_once(props_changed.next()).await {
+                            changes.push(change);
+                        }
+
+                        changes = dedup_signals(changes);
+                    }
+
+                    for change in changes {
+                        match Self::get_update_event(change, properties_proxy).await {
+                            Ok(Some(event)) => {
+                                debug!("[{destination}{path}] received property change: {event:?}");
+                                items.send(tx, Event::Update(destination.to_string(), event))?;
+                            }
+                            Err(e) => {
+                                error!("Error parsing update properties from {destination}{path}: {e:?}");
+                            }
+                            _ => {}
+                        }
+                    }
+
+                    match Self::get_menu_path(properties_proxy).await {
+                        Ok(None) => {
+                            if let Some(old) = menu.take() {
+                                debug!("[{destination}{path}] menu disconnected");
+                                old.abort();
+                                items.send(
+                                    tx,
+                                    Event::Update(destination.to_string(), UpdateEvent::MenuDisconnect),
+                                )?;
+                            }
+                        }
+                        Ok(Some(menu_path)) if menu.as_ref().is_none_or(|menu| menu.path != menu_path) => {
+                            debug!("[{destination}{path}] menu connected: {menu_path}");
+                            if let Some(old) = menu.take() {
+                                old.abort();
+                            }
+                            *menu = Some(Self::connect_menu(destination, &menu_path, connection, tx, items, ctx)?);
+                        }
+                        Err(e) => {
+                            error!("Error getting Menu from {destination}{path}: {e:?}");
+                        }
+                        _ => {}
+                    }
+
+                    match Self::get_item_is_menu(properties_proxy).await {
+                        Ok(Some(value)) if value != *item_is_menu => {
+                            *item_is_menu = value;
+                            debug!("[{destination}{path}] received property change: ItemIsMenu({value})");
+                            items.send(
+                                tx,
+                                Event::Update(destination.to_string(), UpdateEvent::ItemIsMenu(value)),
+                            )?;
+                        }
+                        Err(e) => {
+                            error!("Error getting ItemIsMenu from {destination}{path}: {e:?}");
+                        }
+                        _ => {}
+                    }
+                }
+                Some(signal) = disconnect_stream.next() => {
+                    let args = signal.args()?;
+                    let old = args.old_owner();
+                    let new = args.new_owner();
+
+                    if let (Some(old), None) = (old.as_ref(), new.as_ref()) {
+                        if old.as_str() == owner {
+                            debug!("[{destination}{path}] disconnected");
+
+                            let watcher_proxy = StatusNotifierWatcherProxy::new(connection)
+                                .await
+                                .expect("Failed to open StatusNotifierWatcherProxy");
+
+                            if let Err(error) = watcher_proxy.unregister_status_notifier_item(old).await {
+                                error!("{error:?}");
+                            }
+
+                            if let Some(menu) = menu.take() {
+                                menu.abort();
+                            }
+
+                            items.send(tx, Event::Remove(destination.to_string()))?;
+                            break Ok(());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Subscribes to the signals of the item at `destination` and `path`.
+    async fn subscribe_item(
         destination: &str,
         path: &str,
         connection: &Connection,
-        properties_proxy: PropertiesProxy<'_>,
+    ) -> Result<ItemSignals> {
+        let notifier_item_proxy = StatusNotifierItemProxy::builder(connection)
+            .destination(destination.to_string())?
+            .path(path.to_string())?
+            .build()
+            .await?;
+
+        let dbus_proxy = DBusProxy::new(connection).await?;
+
+        // items can register under a well-known name,
+        // but disconnects are reported for the unique name which owns it
+        let owner = Self::resolve_owner(&dbus_proxy, destination).await?;
+        if owner != destination {
+            debug!("[{destination}{path}] owned by {owner}");
+        }
+
+        Ok(ItemSignals {
+            owner,
+            disconnect_stream: dbus_proxy.receive_name_owner_changed().await?,
+            props_changed: notifier_item_proxy.inner().receive_all_signals().await?,
+        })
+    }
+
+    /// Gets the unique name which owns `destination`.
+    ///
+    /// Unique names are returned as they are.
+    async fn resolve_owner(dbus_proxy: &DBusProxy<'_>, destination: &str) -> Result<String> {
+        if destination.starts_with(':') {
+            return Ok(destination.to_string());
+        }
+
+        let name = zbus::names::BusName::try_from(destination).map_err(zbus::Error::from)?;
+        Ok(dbus_proxy.get_name_owner(name).await?.to_string())
+    }
+
+    /// Gets the update event for a `DBus` properties change message.
+    async fn get_update_event(
+        change: Message,
+        properties_proxy: &PropertiesProxy<'_>,
+    ) -> Result<Option<UpdateEvent>> {
+        use UpdateEvent::{AttentionIcon, Icon, OverlayIcon, Status, Title, Tooltip};
+
+        let header = change.header();
+        let member = header
+            .member()
+            .ok_or(Error::InvalidData("Update message header missing `member`"))?;
+
+        macro_rules! get_property {
+            ($name:expr) => {
+                match properties_proxy
+                    .get(
+                        InterfaceName::from_static_str(PROPERTIES_INTERFACE)
+                            .expect("to be valid interface name"),
+                        $name,
+                    )
+                    .await
+                {
+                    Ok(v) => Ok(Some(v)),
+                    Err(e) => match e {
+                        // Some properties may not be set, and this error will be raised.
+                        zbus::fdo::Error::InvalidArgs(_) => {
+                            warn!("{e}");
+                            Ok(None)
+                        }
+                        _ => Err(Into::<Error>::into(e)),
+                    },
+                }
+            };
+        }
+
+        let property = match member.as_str() {
+            "NewAttentionIcon" => Some(AttentionIcon(
+                get_property!("AttentionIconName")?
+                    .as_ref()
+                    .map(OwnedValueExt::to_string)
+                    .transpose()?,
+            )),
+            "NewIcon" => {
+                let icon_name = match get_property!("IconName") {
+                    Ok(name) => name,
+                    Err(e) => {
+                        warn!("Error getting IconName: {e:?}");
+                        None
+                    }
+                }
+                .as_ref()
+                .map(OwnedValueExt::to_string)
+                .transpose()
+                .ok()
+                .flatten();
+
+                let icon_pixmap = match get_property!("IconPixmap") {
+                    Ok(pixmap) => pixmap,
+                    Err(e) => {
+                        warn!("Error getting IconPixmap: {e:?}");
+                        None
+                    }
+                }
+                .as_deref()
+                .map(Value::downcast_ref::<&Array>)
+                .transpose()?
+                .map(IconPixmap::from_array)
+                .transpose()?;
+
+                Some(Icon {
+                    icon_name,
+                    icon_pixmap,
+                })
+            }
+            "NewOverlayIcon" => Some(OverlayIcon(
+                get_property!("OverlayIconName")?
+                    .as_ref()
+                    .map(OwnedValueExt::to_string)
+                    .transpose()?,
+            )),
+            "NewStatus" => Some(Status(match signal_status(&change) {
+                Some(status) => status,
+                None => get_property!("Status")?
+                    .as_deref()
+                    .map(Value::downcast_ref::<&str>)
+                    .transpose()?
+                    .map(item::Status::from)
+                    .unwrap_or_default(), // NOTE: i'm assuming status is always set
+            })),
+            "NewTitle" => Some(Title(match get_property!("Title") {
+                Ok(title) => title.as_ref().map(OwnedValueExt::to_string).transpose()?,
+                Err(err) => {
+                    warn!("failed to get title: {err}");
+                    None
+                }
+            })),
+            "NewToolTip" => Some(Tooltip(
+                get_property!("ToolTip")?
+                    .as_deref()
+                    .map(Value::downcast_ref::<&Structure>)
+                    .transpose()?
+                    .map(crate::item::Tooltip::try_from)
+                    .transpose()?,
+            )),
+            // not in the spec, and the menu path is polled after every signal anyway
+            "NewMenu" => None,
+            member => {
+                debug!("received unhandled update event: {member}");
+
+                // extensions follow the spec's `New{Property}` naming,
+                // sometimes with a vendor prefix such as `XAyatanaNewLabel`
+                match member.split_once("New") {
+                    Some((prefix, name)) if !name.is_empty() => {
+                        let name = format!("{prefix}{name}");
+                        get_property!(name.as_str())?
+                            .map(|value| UpdateEvent::Other { name, value })
+                    }
+                    _ => None,
+                }
+            }
+        };
+
+        debug!("received tray item update: {member} -> {property:?}");
+
+        Ok(property)
+    }
+
+    /// Gets the current value of the property `name`,
+    /// or `None` if the item does not set it.
+    async fn get_optional_property(
+        properties_proxy: &PropertiesProxy<'_>,
+        name: &str,
+    ) -> Result<Option<OwnedValue>> {
+        match properties_proxy
+            .get(
+                InterfaceName::from_static_str(PROPERTIES_INTERFACE)
+                    .expect("to be valid interface name"),
+                name,
+            )
+            .await
+        {
+            Ok(value) => Ok(Some(value)),
+            Err(zbus::fdo::Error::InvalidArgs(_)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Gets the current value of the `ItemIsMenu` property,
+    /// or `None` if the item does not set it.
+    async fn get_item_is_menu(properties_proxy: &PropertiesProxy<'_>) -> Result<Option<bool>> {
+        Self::get_optional_property(properties_proxy, "ItemIsMenu")
+            .await?
+            .map(bool::try_from)
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Gets the current value of the `Menu` property,
+    /// or `None` if the item does not set it.
+    async fn get_menu_path(properties_proxy: &PropertiesProxy<'_>) -> Result<Option<String>> {
+        Self::get_optional_property(properties_proxy, "Menu")
+            .await?
+            .map(|value| OwnedObjectPath::try_from(value).map(|path| path.to_string()))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    /// Gets the properties of the menu itself, which are not part of its layout:
+    /// the text direction, status and icon theme path.
+    async fn get_menu_properties(
+        dbus_menu_proxy: &DBusMenuProxy<'_>,
+    ) -> (TextDirection, MenuStatus, Vec<String>) {
+        // many applications do not implement these,
+        // so they fall back to the defaults
+        let text_direction = dbus_menu_proxy
+            .text_direction()
+            .await
+            .map_or_else(|_| TextDirection::default(), |value| value.as_str().into());
+        let status = dbus_menu_proxy
+            .status()
+            .await
+            .map_or_else(|_| MenuStatus::default(), |value| value.as_str().into());
+        let icon_theme_path = dbus_menu_proxy.icon_theme_path().await.unwrap_or_default();
+
+        (text_direction, status, icon_theme_path)
+    }
+
+    /// Watches the `DBusMenu` associated with an SNI item.
+    ///
+    /// This gets the initial menu, sending an update event immediately.
+    /// Update events are then sent for any further updates
+    /// until the item is removed.
+    async fn watch_menu(
+        destination: String,
+        menu_path: &str,
+        connection: &Connection,
         tx: broadcast::Sender<Event>,
         items: TrayItemMap,
+        ctx: Context,
     ) -> crate::error::Result<()> {
-        let notifier_item_proxy = StatusNotifierItemProxy::builder(connection)
-            .destination(destination)?
-            .path(path)?
+        let dbus_menu_proxy = DBusMenuProxy::builder(connection)
+            .destination(destination.as_str())?
+            .path(menu_path)?
+            .build()
+            .await?;
+
+        // subscribe before fetching the layout,
+        // so that no updates after the initial menu are missed
+        let mut layout_updated = dbus_menu_proxy.receive_layout_updated().await?;
+        let mut properties_updated = dbus_menu_proxy.receive_items_properties_updated().await?;
+        let mut text_direction_changed = dbus_menu_proxy.receive_text_direction_changed().await;
+        let mut status_changed = dbus_menu_proxy.receive_status_changed().await;
+
+        if ctx.prime_menus {
+            // the layout is fetched next regardless of `needsUpdate`
+            match timeout(ctx.request_timeout(), dbus_menu_proxy.about_to_show(0)).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => debug!("[{destination}{menu_path}] failed to prime menu: {err}"),
+                Err(_) => debug!("[{destination}{menu_path}] timed out priming menu"),
+            }
+        }
+
+        let menu = dbus_menu_proxy
+            .get_layout(0, ctx.menu_depth, MENU_PROPERTIES)
+            .await
+            .map_err(|source| Error::MenuLayout {
+                destination: destination.clone(),
+                source,
+            })?;
+        let mut menu = TrayMenu::try_from(menu)?;
+
+        let (mut text_direction, mut status, icon_theme_path) =
+            Self::get_menu_properties(&dbus_menu_proxy).await;
+
+        menu.text_direction = text_direction;
+        menu.status = status;
+        menu.icon_theme_path.clone_from(&icon_theme_path);
+
+        let mut revision = MenuRevision::default();
+        revision.accept(menu.revision);
+
+        items.send(
+            &tx,
+            Event::Update(destination.to_string(), UpdateEvent::Menu(menu)),
+        )?;
+
+        loop {
+            tokio::select!(
+                Some(update) = layout_updated.next() => {
+                    let args = update.args().ok();
+                    let latest = args.as_ref().map(|args| args.revision);
+                    if let Some(latest) = latest {
+                        revision.signal(latest);
+                    }
+                    // only the subtree under this item has changed
+                    let mut parent = args.as_ref().map_or(0, |args| args.parent);
+                    debug!("[{destination}{menu_path}] layout update (revision {latest:?}, parent {parent})");
+
+                    // collapse bursts of updates into a single fetch
+                    if !ctx.layout_debounce.is_zero() {
+                        let deadline = tokio::time::Instant::now() + ctx.layout_debounce;
+                        while let Ok(Some(update)) = tokio::time::timeout_at(deadline, layout_updated.next()).await {
+                            trace!("[{destination}{menu_path}] debounced layout update");
+                            match update.args() {
+                                Ok(args) => {
+                                    revision.signal(args.revision);
+                                    if args.parent != parent {
+                                        parent = 0;
+                                    }
+                                }
+                                Err(_) => parent = 0,
+                            }
+                        }
+                    }
+
+                    let menu = if parent == 0 {
+                        None
+                    } else {
+                        let get_layout = dbus_menu_proxy.get_layout(parent, ctx.menu_depth, MENU_PROPERTIES);
+
+                        match timeout(ctx.request_timeout(), get_layout).await {
+                            Ok(Ok(menu)) => Some(menu),
+                            Ok(Err(err)) => {
+                                // the parent may have since been removed
+                                warn!("[{destination}{menu_path}] error fetching subtree {parent}, fetching full layout: {err:?}");
+                                parent = 0;
+                                None
+                            }
+                            Err(_) => {
+                                error!("Timeout getting layout");
+                                break;
+                            }
+                        }
+                    };
+
+                    let menu = if let Some(menu) = menu {
+                        debug!("got new menu subtree");
+                        menu
+                    } else {
+                        let get_layout = dbus_menu_proxy.get_layout(0, ctx.menu_depth, MENU_PROPERTIES);
+
+                        match timeout(ctx.request_timeout(), get_layout).await {
+                            Ok(Ok(menu)) => {
+                                debug!("got new menu layout");
+                                menu
+                            }
+                            Ok(Err(err)) => {
+                                error!("error fetching layout: {err:?}");
+                                break;
+                            }
+                            Err(_) => {
+                                error!("Timeout getting layout");
+                                break;
+                            }
+                        }
+                    };
+
+                    let mut menu = TrayMenu::try_from(menu)?;
+                    menu.text_direction = text_direction;
+                    menu.status = status;
+                    menu.icon_theme_path.clone_from(&icon_theme_path);
+
+                    if !revision.accept(menu.revision) {
+                        debug!("[{destination}{menu_path}] ignoring stale layout (revision {})", menu.revision);
+                        continue;
+                    }
+
+                    debug!("sending new menu for '{destination}'");
+                    trace!("new menu for '{destination}': {menu:?}");
+
+                    let update = if parent == 0 {
+                        UpdateEvent::Menu(menu)
+                    } else {
+                        UpdateEvent::MenuSubtree { parent_id: parent, menu }
+                    };
+
+                    items.send(&tx, Event::Update(destination.to_string(), update))?;
+                }
+                Some(change) = properties_updated.next() => {
+                    let body = change.message().body();
+                    let update: PropertiesUpdate= body.deserialize::<PropertiesUpdate>()?;
+                    let diffs = Vec::try_from(update)?;
+
+                    items.send(
+                        &tx,
+                        Event::Update(destination.to_string(), UpdateEvent::MenuDiff(diffs)),
+                    )?;
+                }
+                Some(change) = text_direction_changed.next() => {
+                    let Ok(value) = change.get().await else {
+                        continue;
+                    };
+
+                    let value = TextDirection::from(value.as_str());
+                    if value != text_direction {
+                        text_direction = value;
+                        items.send(
+                            &tx,
+                            Event::Update(destination.to_string(), UpdateEvent::MenuTextDirection(value)),
+                        )?;
+                    }
+                }
+                Some(change) = status_changed.next() => {
+                    let Ok(value) = change.get().await else {
+                        continue;
+                    };
+
+                    let value = MenuStatus::from(value.as_str());
+                    if value != status {
+                        status = value;
+                        items.send(
+                            &tx,
+                            Event::Update(destination.to_string(), UpdateEvent::MenuStatus(value)),
+                        )?;
+                    }
+                }
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Gets the current bus connection,
+    /// for making other calls without opening a second connection.
+    ///
+    /// The connection is shared with the client, so it should not be closed.
+    /// It may be replaced if the client reconnects,
+    /// so get it again rather than holding on to it.
+    pub fn connection(&self) -> Connection {
+        self.connection.lock_or_recover().clone()
+    }
+
+    async fn get_notifier_item_proxy(
+        &self,
+        address: String,
+    ) -> crate::error::Result<StatusNotifierItemProxy<'_>> {
+        let proxy = StatusNotifierItemProxy::builder(&self.connection())
+            .destination(address)?
+            .path(ITEM_OBJECT)?
+            .build()
+            .await?;
+        Ok(proxy)
+    }
+
+    async fn get_menu_proxy(
+        &self,
+        address: String,
+        menu_path: String,
+    ) -> crate::error::Result<DBusMenuProxy<'_>> {
+        let proxy = DBusMenuProxy::builder(&self.connection())
+            .destination(address)?
+            .path(menu_path)?
             .build()
             .await?;
+        Ok(proxy)
+    }
+
+    /// Subscribes to the events broadcast channel,
+    /// returning a new receiver.
+    ///
+    /// Events are not timestamped.
+    /// Use [`Client::subscribe_sequenced`] to get the time each event was sent.
+    ///
+    /// Once the client is dropped, the receiver will close.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
 
-        let dbus_proxy = DBusProxy::new(connection).await?;
+    /// Subscribes to the events broadcast channel as a stream,
+    /// yielding each event in order.
+    ///
+    /// If events are not consumed quickly enough, the oldest are skipped.
+    /// The stream ends once the client is dropped.
+    ///
+    /// The stream can be merged with other streams,
+    /// for example using [`StreamExt::or`] or `futures::stream::select`,
+    /// to handle tray events alongside other inputs in a single loop.
+    /// It is not [`Unpin`], so pin it first using [`std::pin::pin!`]
+    /// if the combinator requires that.
+    pub fn event_stream(&self) -> impl Stream<Item = Event> + Send + 'static {
+        receiver_stream(self.subscribe())
+    }
 
-        let mut disconnect_stream = dbus_proxy.receive_name_owner_changed().await?;
-        let mut props_changed = notifier_item_proxy.inner().receive_all_signals().await?;
+    /// Subscribes to the events broadcast channel,
+    /// with each event wrapped in an [`EventEnvelope`]
+    /// carrying a sequence number and the time it was sent.
+    ///
+    /// Sequence numbers are shared between all sequenced receivers
+    /// and continue across reconnects,
+    /// so they can be used to detect missed events and resync.
+    #[must_use]
+    pub fn subscribe_sequenced(&self) -> broadcast::Receiver<EventEnvelope> {
+        self.envelope_tx.subscribe()
+    }
 
-        loop {
-            tokio::select! {
-                Some(change) = props_changed.next() => {
-                    match Self::get_update_event(change, &properties_proxy).await {
-                        Ok(Some(event)) => {
-                                cfg_if::cfg_if! {
-                                    if #[cfg(feature = "data")] {
-                                        items.apply_update_event(destination, &event);
-                                    }
-                                }
-                                debug!("[{destination}{path}] received property change: {event:?}");
-                                tx.send(Event::Update(destination.to_string(), event))?;
-                            }
-                        Err(e) => {
-                            error!("Error parsing update properties from {destination}{path}: {e:?}");
-                        }
-                        _ => {}
+    /// Gets the current items and subscribes to the events broadcast channel at the same time.
+    ///
+    /// Unlike calling [`Client::subscribe`] then [`Client::items`],
+    /// this guarantees the receiver gets every event after the snapshot was taken,
+    /// and none of the events already reflected in it.
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn subscribe_with_snapshot(&self) -> (crate::data::BaseMap, broadcast::Receiver<Event>) {
+        self.items.snapshot_and_subscribe(&self.tx)
+    }
+
+    /// Subscribes to snapshots of all current items as a stream,
+    /// for consumers which rebuild their whole UI on each change
+    /// rather than applying events.
+    ///
+    /// The current items are yielded immediately,
+    /// followed by a new snapshot after each batch of events.
+    /// Events which arrive while a snapshot is being handled
+    /// are collapsed into the next one.
+    /// The stream ends once the client is dropped.
+    #[cfg(feature = "data")]
+    pub fn snapshot_stream(&self) -> impl Stream<Item = crate::data::BaseMap> + Send + 'static {
+        let (snapshot, rx) = self.subscribe_with_snapshot();
+        let map = self.items.get_map();
+
+        futures_lite::stream::unfold(
+            (Some(snapshot), rx, map),
+            |(snapshot, mut rx, map)| async move {
+                if let Some(snapshot) = snapshot {
+                    return Some((snapshot, (None, rx, map)));
+                }
+
+                // wait for a change, then take any others already queued
+                loop {
+                    match rx.recv().await {
+                        Ok(Event::Heartbeat) => {}
+                        Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => break,
+                        Err(broadcast::error::RecvError::Closed) => return None,
                     }
                 }
-                Some(signal) = disconnect_stream.next() => {
-                    let args = signal.args()?;
-                    let old = args.old_owner();
-                    let new = args.new_owner();
 
-                    if let (Some(old), None) = (old.as_ref(), new.as_ref()) {
-                        if old == destination {
-                            debug!("[{destination}{path}] disconnected");
+                while matches!(
+                    rx.try_recv(),
+                    Ok(_) | Err(broadcast::error::TryRecvError::Lagged(_))
+                ) {}
 
-                            let watcher_proxy = StatusNotifierWatcherProxy::new(connection)
-                                .await
-                                .expect("Failed to open StatusNotifierWatcherProxy");
+                let snapshot = map.lock_or_recover().clone();
+                Some((snapshot, (None, rx, map)))
+            },
+        )
+    }
 
-                            if let Err(error) = watcher_proxy.unregister_status_notifier_item(old).await {
-                                error!("{error:?}");
-                            }
+    /// Gets all current items, including their menus if present.
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn items(&self) -> std::sync::Arc<std::sync::Mutex<crate::data::BaseMap>> {
+        self.items.get_map()
+    }
+
+    /// Gets the current state of a single item, including its menu if present.
+    ///
+    /// Returns `None` if there is no item with the given address.
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn get_item(&self, address: &str) -> Option<(StatusNotifierItem, Option<TrayMenu>)> {
+        self.items.get(address)
+    }
 
+    /// Waits for an item with the SNI id `id` to be added,
+    /// for example after launching the application which owns it.
+    ///
+    /// Resolves immediately if such an item already exists.
+    /// Items hidden by a filter are only found once they are shown.
+    ///
+    /// # Errors
+    ///
+    /// If no matching item is added within `wait`.
+    pub async fn wait_for_item(&self, id: &str, wait: Duration) -> Result<StatusNotifierItem> {
+        use broadcast::error::RecvError;
+
+        #[cfg(feature = "data")]
+        let find = || {
+            self.items
+                .get_map()
+                .lock_or_recover()
+                .values()
+                .find(|(item, _)| item.id == id)
+                .map(|(item, _)| item.clone())
+        };
 
-                            items.remove_item(destination);
+        cfg_if::cfg_if! {
+            if #[cfg(feature = "data")] {
+                let (snapshot, mut rx) = self.subscribe_with_snapshot();
+                if let Some((item, _)) = snapshot.into_values().find(|(item, _)| item.id == id) {
+                    return Ok(item);
+                }
+            } else {
+                let mut rx = self.subscribe();
+            }
+        }
 
-                            tx.send(Event::Remove(destination.to_string()))?;
-                            break Ok(());
+        let added = async {
+            loop {
+                match rx.recv().await {
+                    Ok(Event::Add { item, .. }) if item.id == id => break Some(*item),
+                    Err(RecvError::Lagged(_)) => {
+                        // the add may have been skipped, so check the state instead
+                        #[cfg(feature = "data")]
+                        if let Some(item) = find() {
+                            break Some(item);
                         }
                     }
+                    Ok(_) => {}
+                    Err(RecvError::Closed) => break None,
                 }
             }
-        }
+        };
+
+        timeout(wait, added)
+            .await
+            .ok()
+            .flatten()
+            .ok_or_else(|| Error::ItemTimeout(id.to_string()))
     }
 
-    /// Gets the update event for a `DBus` properties change message.
-    async fn get_update_event(
-        change: Message,
-        properties_proxy: &PropertiesProxy<'_>,
-    ) -> Result<Option<UpdateEvent>> {
-        use UpdateEvent::{AttentionIcon, Icon, OverlayIcon, Status, Title, Tooltip};
+    /// Fetches an item's properties directly from the item,
+    /// bypassing the cached state.
+    ///
+    /// This is useful to get the ground truth,
+    /// for example when rebuilding a UI or checking the cache for drift.
+    /// The cache is not updated.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the item does not respond within the request timeout,
+    /// or if its properties are invalid.
+    pub async fn fetch_item_properties(&self, address: &str) -> Result<StatusNotifierItem> {
+        let (destination, path) = parse_address(address);
 
-        let header = change.header();
-        let member = header
-            .member()
-            .ok_or(Error::InvalidData("Update message header missing `member`"))?;
+        let properties_proxy = PropertiesProxy::builder(&self.connection())
+            .destination(destination.to_string())?
+            .path(path.clone())?
+            .build()
+            .await?;
 
-        macro_rules! get_property {
-            ($name:expr) => {
-                match properties_proxy
-                    .get(
-                        InterfaceName::from_static_str(PROPERTIES_INTERFACE)
-                            .expect("to be valid interface name"),
-                        $name,
-                    )
-                    .await
-                {
-                    Ok(v) => Ok(Some(v)),
-                    Err(e) => match e {
-                        // Some properties may not be set, and this error will be raised.
-                        zbus::fdo::Error::InvalidArgs(_) => {
-                            warn!("{e}");
-                            Ok(None)
-                        }
-                        _ => Err(Into::<Error>::into(e)),
-                    },
-                }
-            };
+        timeout(
+            self.ctx.request_timeout(),
+            Self::get_item_properties(destination, &path, &properties_proxy),
+        )
+        .await
+        .unwrap_or_else(|_| {
+            Err(Error::ItemProperties {
+                destination: destination.to_string(),
+                source: zbus::fdo::Error::TimedOut("timed out fetching properties".to_string()),
+            })
+        })
+    }
+
+    /// Sends a scroll on the item, for example from the mouse wheel.
+    ///
+    /// There is no property advertising whether an item handles scrolling,
+    /// so this can be used to detect it:
+    /// items which do not implement the method return [`Error::Unsupported`],
+    /// and UIs may then disable scrolling for them.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created,
+    /// if the item does not support scrolling,
+    /// or if the request fails or times out.
+    pub async fn try_scroll(
+        &self,
+        address: String,
+        delta: i32,
+        orientation: ScrollOrientation,
+    ) -> crate::error::Result<()> {
+        let proxy = self.get_notifier_item_proxy(address.clone()).await?;
+
+        let res = timeout(
+            self.ctx.request_timeout(),
+            proxy.scroll(delta, orientation.as_str()),
+        )
+        .await
+        .map_err(|_| zbus::Error::from(zbus::fdo::Error::TimedOut("scroll".to_string())))?;
+
+        match res.map_err(zbus::fdo::Error::from) {
+            Ok(()) => Ok(()),
+            Err(zbus::fdo::Error::UnknownMethod(_)) => Err(Error::Unsupported {
+                address,
+                method: "Scroll",
+            }),
+            Err(err) => Err(err.into()),
         }
+    }
 
-        let property = match member.as_str() {
-            "NewAttentionIcon" => Some(AttentionIcon(
-                get_property!("AttentionIconName")?
-                    .as_ref()
-                    .map(OwnedValueExt::to_string)
-                    .transpose()?,
-            )),
-            "NewIcon" => {
-                let icon_name = match get_property!("IconName") {
-                    Ok(name) => name,
-                    Err(e) => {
-                        warn!("Error getting IconName: {e:?}");
-                        None
-                    }
-                }
-                .as_ref()
-                .map(OwnedValueExt::to_string)
-                .transpose()
-                .ok()
-                .flatten();
+    /// One should call this method with id=0 when opening the root menu.
+    ///
+    /// ID refers to the menuitem id.
+    /// Returns `needsUpdate`
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created.
+    pub async fn about_to_show_menuitem(
+        &self,
+        address: String,
+        menu_path: String,
+        id: i32,
+    ) -> crate::error::Result<bool> {
+        let proxy = self.get_menu_proxy(address, menu_path).await?;
+        Ok(proxy.about_to_show(id).await?)
+    }
 
-                let icon_pixmap = match get_property!("IconPixmap") {
-                    Ok(pixmap) => pixmap,
-                    Err(e) => {
-                        warn!("Error getting IconPixmap: {e:?}");
-                        None
-                    }
-                }
-                .as_deref()
-                .map(Value::downcast_ref::<&Array>)
-                .transpose()?
-                .map(IconPixmap::from_array)
-                .transpose()?;
+    /// Calls [`Client::about_to_show_menuitem`] for several menu items in a single call,
+    /// such as to prime every top-level submenu at once.
+    ///
+    /// Returns the ids of the items which need updating,
+    /// followed by the ids of any items which could not be found.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created.
+    pub async fn about_to_show_group(
+        &self,
+        address: String,
+        menu_path: String,
+        ids: Vec<i32>,
+    ) -> crate::error::Result<(Vec<i32>, Vec<i32>)> {
+        let proxy = self.get_menu_proxy(address, menu_path).await?;
+        Ok(proxy.about_to_show_group(&ids).await?)
+    }
 
-                Some(Icon {
-                    icon_name,
-                    icon_pixmap,
-                })
-            }
-            "NewOverlayIcon" => Some(OverlayIcon(
-                get_property!("OverlayIconName")?
-                    .as_ref()
-                    .map(OwnedValueExt::to_string)
-                    .transpose()?,
-            )),
-            "NewStatus" => Some(Status(
-                get_property!("Status")?
-                    .as_deref()
-                    .map(Value::downcast_ref::<&str>)
-                    .transpose()?
-                    .map(item::Status::from)
-                    .unwrap_or_default(), // NOTE: i'm assuming status is always set
-            )),
-            "NewTitle" => Some(Title(
-                get_property!("Title")?
-                    .as_ref()
-                    .map(OwnedValueExt::to_string)
-                    .transpose()?,
-            )),
-            "NewToolTip" => Some(Tooltip(
-                get_property!("ToolTip")?
-                    .as_deref()
-                    .map(Value::downcast_ref::<&Structure>)
-                    .transpose()?
-                    .map(crate::item::Tooltip::try_from)
-                    .transpose()?,
-            )),
-            _ => {
-                warn!("received unhandled update event: {member}");
-                None
-            }
-        };
+    /// Fetches the current value of `properties` for each menu item in `ids`, in a single call.
+    ///
+    /// This is much cheaper than fetching the whole layout
+    /// when only a few items are of interest.
+    /// The values are returned as diffs, which can be applied to a cached menu
+    /// with [`TrayMenu::apply_diff`].
+    /// If `properties` is empty, all properties are fetched.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created,
+    /// or if the returned properties are invalid.
+    pub async fn get_group_properties(
+        &self,
+        address: String,
+        menu_path: String,
+        ids: Vec<i32>,
+        properties: Vec<String>,
+    ) -> crate::error::Result<Vec<MenuDiff>> {
+        let proxy = self.get_menu_proxy(address, menu_path).await?;
 
-        debug!("received tray item update: {member} -> {property:?}");
+        let properties = properties.iter().map(String::as_str).collect::<Vec<_>>();
+        let groups = proxy.get_group_properties(&ids, &properties).await?;
 
-        Ok(property)
+        groups
+            .into_iter()
+            .map(|(id, properties)| MenuDiff::from_properties(id, &properties))
+            .collect()
     }
 
-    /// Watches the `DBusMenu` associated with an SNI item.
+    /// Sends several menu events in a single call,
+    /// such as closing one submenu and opening another.
     ///
-    /// This gets the initial menu, sending an update event immediately.
-    /// Update events are then sent for any further updates
-    /// until the item is removed.
-    async fn watch_menu(
-        destination: String,
-        menu_path: &str,
-        connection: &Connection,
-        tx: broadcast::Sender<Event>,
-        items: TrayItemMap,
-    ) -> crate::error::Result<()> {
-        let dbus_menu_proxy = DBusMenuProxy::builder(connection)
-            .destination(destination.as_str())?
-            .path(menu_path)?
-            .build()
-            .await?;
+    /// Each event is given as the menu item id, the event type,
+    /// and a timestamp in seconds.
+    /// Returns the ids of any items which could not be found.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created,
+    /// or if the application fails to handle all of the events.
+    pub async fn menu_event_group(
+        &self,
+        address: String,
+        menu_path: String,
+        events: Vec<(i32, MenuEventType, u32)>,
+    ) -> crate::error::Result<Vec<i32>> {
+        let proxy = self.get_menu_proxy(address, menu_path).await?;
 
-        let menu = dbus_menu_proxy.get_layout(0, 10, &[]).await?;
-        let menu = TrayMenu::try_from(menu)?;
+        let events = events
+            .into_iter()
+            .map(|(id, event_id, timestamp)| (id, event_id.as_str(), Value::I32(0), timestamp))
+            .collect::<Vec<_>>();
 
-        items.update_menu(&destination, &menu);
+        Ok(proxy.event_group(&events).await?)
+    }
 
-        tx.send(Event::Update(
-            destination.to_string(),
-            UpdateEvent::Menu(menu),
-        ))?;
+    /// Fetches the direct children of the menu item with id `parent_id`.
+    ///
+    /// The returned menu's `submenus` are the children of `parent_id`,
+    /// without any of their own children populated.
+    ///
+    /// This is intended for menus which populate submenus lazily
+    /// (those with `children_display` set to `submenu`).
+    /// Call [`Client::about_to_show_menuitem`] for the submenu first,
+    /// then this to get its contents.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created,
+    /// or if the returned layout is invalid.
+    pub async fn get_submenu(
+        &self,
+        address: String,
+        menu_path: String,
+        parent_id: i32,
+    ) -> crate::error::Result<TrayMenu> {
+        let proxy = self.get_menu_proxy(address, menu_path).await?;
+        let layout = proxy.get_layout(parent_id, 1, MENU_PROPERTIES).await?;
+        TrayMenu::try_from(layout)
+    }
 
-        let mut layout_updated = dbus_menu_proxy.receive_layout_updated().await?;
-        let mut properties_updated = dbus_menu_proxy.receive_items_properties_updated().await?;
+    /// Gets the addresses of all items currently registered on the watcher.
+    ///
+    /// This queries the watcher directly,
+    /// so includes items which have not finished loading
+    /// and items hidden by a filter.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the watcher cannot be reached.
+    pub async fn registered_items(&self) -> Result<Vec<String>> {
+        let watcher_proxy = StatusNotifierWatcherProxy::new(&self.connection()).await?;
+        Ok(watcher_proxy.registered_status_notifier_items().await?)
+    }
 
-        loop {
-            tokio::select!(
-                Some(_) = layout_updated.next() => {
-                    debug!("[{destination}{menu_path}] layout update");
+    /// Gets the addresses of all items registered on the watcher
+    /// which are in `category`.
+    ///
+    /// Like [`Client::registered_items`], this queries the watcher directly.
+    /// The category of each item is then fetched from the item itself,
+    /// costing a call per item the first time it is seen.
+    /// Categories cannot change, so they are cached after that.
+    /// Items which do not respond within the request timeout are left out.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the watcher cannot be reached.
+    pub async fn registered_items_by_category(&self, category: Category) -> Result<Vec<String>> {
+        let addresses = self.registered_items().await?;
+
+        // forget items which have gone, so the cache does not grow forever
+        self.categories
+            .lock_or_recover()
+            .retain(|address, _| addresses.contains(address));
+
+        let mut matching = Vec::new();
+        for address in addresses {
+            let cached = self.categories.lock_or_recover().get(&address).copied();
+            let item_category = match cached {
+                Some(item_category) => item_category,
+                None => match self.fetch_category(&address).await {
+                    Ok(item_category) => {
+                        self.categories
+                            .lock_or_recover()
+                            .insert(address.clone(), item_category);
+                        item_category
+                    }
+                    Err(err) => {
+                        warn!("failed to get category of {address}: {err}");
+                        continue;
+                    }
+                },
+            };
 
-                    let get_layout = dbus_menu_proxy.get_layout(0, 10, &[]);
+            if item_category == category {
+                matching.push(address);
+            }
+        }
 
-                    let menu = match timeout(Duration::from_secs(1), get_layout).await {
-                        Ok(Ok(menu)) => {
-                            debug!("got new menu layout");
-                            menu
-                        }
-                        Ok(Err(err)) => {
-                            error!("error fetching layout: {err:?}");
-                            break;
-                        }
-                        Err(_) => {
-                            error!("Timeout getting layout");
-                            break;
-                        }
-                    };
+        Ok(matching)
+    }
 
-                    let menu = TrayMenu::try_from(menu)?;
+    /// Fetches the `Category` property of the item at `address`.
+    async fn fetch_category(&self, address: &str) -> Result<Category> {
+        let (destination, path) = parse_address(address);
 
-                    items.update_menu(&destination, &menu);
+        let properties_proxy = PropertiesProxy::builder(&self.connection())
+            .destination(destination.to_string())?
+            .path(path)?
+            .build()
+            .await?;
 
-                    debug!("sending new menu for '{destination}'");
-                    trace!("new menu for '{destination}': {menu:?}");
-                    tx.send(Event::Update(
-                        destination.to_string(),
-                        UpdateEvent::Menu(menu),
-                    ))?;
-                }
-                Some(change) = properties_updated.next() => {
-                    let body = change.message().body();
-                    let update: PropertiesUpdate= body.deserialize::<PropertiesUpdate>()?;
-                    let diffs = Vec::try_from(update)?;
+        let value = timeout(
+            self.ctx.request_timeout(),
+            Self::get_optional_property(&properties_proxy, "Category"),
+        )
+        .await
+        .map_err(|_| Error::ItemProperties {
+            destination: destination.to_string(),
+            source: zbus::fdo::Error::TimedOut("timed out fetching category".to_string()),
+        })??;
+
+        Ok(value
+            .as_ref()
+            .map(OwnedValueExt::to_string)
+            .transpose()?
+            .map(|category| Category::from(category.as_str()))
+            .unwrap_or_default())
+    }
 
-                    cfg_if::cfg_if! {
-                        if #[cfg(feature = "data")] {
-                            if let Some((_, Some(menu))) = items
-                                .get_map()
-                                .lock()
-                                .expect("mutex lock should succeed")
-                                .get_mut(&destination)
-                            {
-                                apply_menu_diffs(menu, &diffs);
-                            } else {
-                                error!("could not find item in state");
-                            }
-                        }
-                    }
+    /// Checks whether the watcher has any host registered.
+    ///
+    /// Note this includes the host registered by this client itself.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the watcher cannot be reached.
+    pub async fn is_host_registered(&self) -> Result<bool> {
+        let watcher_proxy = StatusNotifierWatcherProxy::new(&self.connection()).await?;
+        Ok(watcher_proxy.is_status_notifier_host_registered().await?)
+    }
 
-                    tx.send(Event::Update(
-                        destination.to_string(),
-                        UpdateEvent::MenuDiff(diffs),
-                    ))?;
+    /// Gets the well-known name the host is registered under,
+    /// in the format `{prefix}-{pid}-{i}`.
+    /// The prefix defaults to [`DEFAULT_HOST_NAME_PREFIX`],
+    /// and can be changed using [`ClientBuilder::host_name_prefix`].
+    ///
+    /// This is generated when the client connects,
+    /// so may change if the client reconnects.
+    #[must_use]
+    pub fn host_name(&self) -> String {
+        self.ctx
+            .host_name
+            .lock_or_recover()
+            .as_ref()
+            .map(ToString::to_string)
+            .unwrap_or_default()
+    }
 
-                    // FIXME: Menu cache gonna be out of sync
-                }
-            );
-        }
+    /// Stops the client, aborting all of its background tasks
+    /// and releasing the host's well-known name on the bus.
+    ///
+    /// Subscribers stop receiving events once this is called.
+    /// Dropping the client also aborts its tasks and releases the name,
+    /// but cannot report errors and requires a Tokio runtime to be running.
+    ///
+    /// # Errors
+    ///
+    /// If releasing the name fails, this method will return an error.
+    /// The tasks are aborted regardless.
+    pub async fn shutdown(self) -> Result<()> {
+        self.ctx.tasks.abort_all();
+        self.release_host_name().await?;
 
+        debug!("tray client shut down");
         Ok(())
     }
 
-    async fn get_notifier_item_proxy(
-        &self,
-        address: String,
-    ) -> crate::error::Result<StatusNotifierItemProxy<'_>> {
-        let proxy = StatusNotifierItemProxy::builder(&self.connection)
-            .destination(address)?
-            .path(ITEM_OBJECT)?
-            .build()
-            .await?;
-        Ok(proxy)
+    /// Releases the host's well-known name, if it is still held.
+    ///
+    /// The name is taken immediately, so the returned future
+    /// does not borrow the client and can be spawned.
+    pub(crate) fn release_host_name(&self) -> impl Future<Output = Result<()>> + Send + 'static {
+        let host_name = self.ctx.host_name.lock_or_recover().take();
+        let connection = self.connection();
+
+        async move {
+            if let Some(host_name) = host_name {
+                connection.release_name(host_name).await?;
+            }
+
+            Ok(())
+        }
     }
 
-    async fn get_menu_proxy(
+    /// Fetches the full menu layout,
+    /// replacing the cached menu and sending a menu update event.
+    ///
+    /// This is an escape hatch for when the cached menu is suspected
+    /// to be out of sync, for example after a diff which could not be applied.
+    ///
+    /// # Errors
+    ///
+    /// Errors if the proxy cannot be created,
+    /// if the returned layout is invalid,
+    /// or if sending the update event fails.
+    pub async fn refresh_menu(
         &self,
         address: String,
         menu_path: String,
-    ) -> crate::error::Result<DBusMenuProxy<'_>> {
-        let proxy = DBusMenuProxy::builder(&self.connection)
-            .destination(address)?
-            .path(menu_path)?
-            .build()
-            .await?;
-        Ok(proxy)
+    ) -> crate::error::Result<TrayMenu> {
+        let proxy = self.get_menu_proxy(address.clone(), menu_path).await?;
+        let layout = proxy.get_layout(0, -1, MENU_PROPERTIES).await?;
+        let mut menu = TrayMenu::try_from(layout)?;
+        (menu.text_direction, menu.status, menu.icon_theme_path) =
+            Self::get_menu_properties(&proxy).await;
+
+        let event = Event::Update(address, UpdateEvent::Menu(menu.clone()));
+
+        self.items.send(&self.tx, event)?;
+
+        Ok(menu)
     }
 
-    /// Subscribes to the events broadcast channel,
-    /// returning a new receiver.
+    /// Sets how long to wait for an item to respond to a request,
+    /// such as an activation or fetching its menu layout.
     ///
-    /// Once the client is dropped, the receiver will close.
-    #[must_use]
-    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
-        self.tx.subscribe()
+    /// This applies to requests made after the call,
+    /// including those made by already watched menus.
+    pub fn set_request_timeout(&self, timeout: Duration) {
+        *self.ctx.request_timeout.lock_or_recover() = timeout;
     }
 
-    /// Gets all current items, including their menus if present.
+    /// Subscribes to changes of the owner of `address` on the bus.
+    async fn receive_owner_changed(
+        &self,
+        address: &str,
+    ) -> crate::error::Result<zbus::fdo::NameOwnerChangedStream> {
+        let dbus_proxy = DBusProxy::new(&self.connection()).await?;
+        Ok(dbus_proxy
+            .receive_name_owner_changed_with_args(&[(0, address)])
+            .await?)
+    }
+
+    /// Activates the item in the cached menu of the item at `address`
+    /// whose shortcut is `key` pressed with `modifiers`,
+    /// such as for global menu style keyboard handling.
+    ///
+    /// See [`TrayMenu::find_by_shortcut`] for how shortcuts are matched.
+    /// Returns `false` if no item matched,
+    /// including when the item has no menu or it has not been loaded.
+    ///
+    /// # Errors
+    ///
+    /// See [`Client::activate`].
     #[cfg(feature = "data")]
-    #[must_use]
-    pub fn items(&self) -> std::sync::Arc<std::sync::Mutex<crate::data::BaseMap>> {
-        self.items.get_map()
+    pub async fn activate_by_shortcut(
+        &self,
+        address: &str,
+        modifiers: &[String],
+        key: &str,
+    ) -> Result<bool> {
+        let Some((item, Some(menu))) = self.get_item(address) else {
+            return Ok(false);
+        };
+
+        let (Some(menu_path), Some(shortcut_item)) =
+            (item.menu, menu.find_by_shortcut(modifiers, key))
+        else {
+            return Ok(false);
+        };
+
+        self.activate(ActivateRequest::MenuItem {
+            address: address.to_string(),
+            menu_path,
+            submenu_id: shortcut_item.id,
+        })
+        .await?;
+
+        Ok(true)
     }
 
-    /// One should call this method with id=0 when opening the root menu.
+    /// Sends an activate request for `item`,
+    /// which should be from the menu at `menu_path` on `address`.
     ///
-    /// ID refers to the menuitem id.
-    /// Returns `needsUpdate`
+    /// This is equivalent to [`Client::activate`]
+    /// with an [`ActivateRequest::MenuItem`] using the item's id.
     ///
     /// # Errors
     ///
-    /// Errors if the proxy cannot be created.
-    pub async fn about_to_show_menuitem(
+    /// See [`Client::activate`].
+    pub async fn activate_menu_item(
         &self,
         address: String,
         menu_path: String,
-        id: i32,
-    ) -> crate::error::Result<bool> {
-        let proxy = self.get_menu_proxy(address, menu_path).await?;
-        Ok(proxy.about_to_show(id).await?)
+        item: &MenuItem,
+    ) -> crate::error::Result<()> {
+        self.activate(ActivateRequest::MenuItem {
+            address,
+            menu_path,
+            submenu_id: item.id,
+        })
+        .await
     }
 
     /// Sends an activate request for a menu item.
     ///
+    /// Timeouts and errors replied by the application are only logged.
+    /// Use [`Client::activate_with_outcome`] to find out whether the request was handled.
+    ///
     /// # Errors
     ///
-    /// The method will return an error if the connection to the `DBus` object fails,
-    /// or if sending the event fails for any reason.
+    /// See [`Client::activate_with_outcome`].
     ///
     /// # Panics
     ///
     /// If the system time is somehow before the Unix epoch.
     pub async fn activate(&self, req: ActivateRequest) -> crate::error::Result<()> {
+        match self.activate_with_outcome(req).await? {
+            ActivateOutcome::Acknowledged => {}
+            ActivateOutcome::TimedOut => error!("Timed out sending activate event"),
+            ActivateOutcome::Rejected(reason) => warn!("activate event rejected: {reason}"),
+        }
+
+        Ok(())
+    }
+
+    /// Sends an activate request,
+    /// returning whether the application handled it.
+    ///
+    /// Applications can reject a request, such as a click on a disabled menu item,
+    /// which a UI may want to give feedback for.
+    ///
+    /// # Errors
+    ///
+    /// The method will return an error if the connection to the `DBus` object fails.
+    ///
+    /// If the owner of the item's address changes while the request is in flight,
+    /// for example because the application restarted,
+    /// [`Error::ItemGone`] is returned.
+    ///
+    /// Positions for default and secondary activations are translated
+    /// using the client's [`CoordinateSpace`].
+    /// Negative positions are sent as they are,
+    /// as outputs may be left of or above the origin.
+    /// If the translated position cannot be represented,
+    /// [`Error::InvalidCoordinates`] is returned without sending anything.
+    ///
+    /// # Panics
+    ///
+    /// If the system time is somehow before the Unix epoch.
+    pub async fn activate_with_outcome(
+        &self,
+        req: ActivateRequest,
+    ) -> crate::error::Result<ActivateOutcome> {
         macro_rules! timeout_event {
-            ($event:expr) => {
-                if timeout(Duration::from_secs(1), $event).await.is_err() {
-                    error!("Timed out sending activate event");
+            ($address:expr, $event:expr) => {{
+                let mut owner_changed = self.receive_owner_changed(&$address).await?;
+                tokio::select! {
+                    res = timeout(self.ctx.request_timeout(), $event) => match res {
+                        Ok(Ok(_)) => ActivateOutcome::Acknowledged,
+                        Ok(Err(err)) => ActivateOutcome::Rejected(err.to_string()),
+                        Err(_) => ActivateOutcome::TimedOut,
+                    },
+                    Some(_) = owner_changed.next() => {
+                        return Err(Error::ItemGone($address));
+                    }
                 }
-            };
+            }};
         }
-        match req {
+        let outcome = match req {
             ActivateRequest::MenuItem {
                 address,
                 menu_path,
                 submenu_id,
             } => {
-                let proxy = self.get_menu_proxy(address, menu_path).await?;
+                return Box::pin(self.activate_with_outcome(ActivateRequest::MenuEvent {
+                    address,
+                    menu_path,
+                    submenu_id,
+                    event_id: MenuEventType::Clicked,
+                }))
+                .await;
+            }
+            ActivateRequest::MenuEvent {
+                address,
+                menu_path,
+                submenu_id,
+                event_id,
+            } => {
+                let proxy = self.get_menu_proxy(address.clone(), menu_path).await?;
                 let timestamp = SystemTime::now()
                     .duration_since(UNIX_EPOCH)
                     .expect("time should flow forwards");
 
                 let event = proxy.event(
                     submenu_id,
-                    "clicked",
+                    event_id.as_str(),
                     &Value::I32(0),
                     timestamp.as_secs() as u32,
                 );
 
-                timeout_event!(event);
+                timeout_event!(address, event)
             }
             ActivateRequest::Default { address, x, y } => {
-                let proxy = self.get_notifier_item_proxy(address).await?;
+                let (x, y) = self.ctx.screen_position(&address, x, y)?;
+                let proxy = self.get_notifier_item_proxy(address.clone()).await?;
                 let event = proxy.activate(x, y);
 
-                timeout_event!(event);
+                timeout_event!(address, event)
             }
             ActivateRequest::Secondary { address, x, y } => {
-                let proxy = self.get_notifier_item_proxy(address).await?;
+                let (x, y) = self.ctx.screen_position(&address, x, y)?;
+                let proxy = self.get_notifier_item_proxy(address.clone()).await?;
                 let event = proxy.secondary_activate(x, y);
 
-                timeout_event!(event);
+                timeout_event!(address, event)
+            }
+        };
+
+        Ok(outcome)
+    }
+}
+
+impl Drop for Client {
+    fn drop(&mut self) {
+        self.ctx.tasks.abort_all();
+
+        // the connection may be shared and outlive the client,
+        // in which case the bus would never release the name
+        let host_name = self.ctx.host_name.lock_or_recover().take();
+
+        if let Some(host_name) = host_name {
+            let Ok(handle) = tokio::runtime::Handle::try_current() else {
+                warn!("no runtime to release host name {host_name} on drop");
+                return;
+            };
+
+            let connection = self.connection();
+            handle.spawn(async move {
+                if let Err(err) = connection.release_name(host_name).await {
+                    warn!("failed to release host name: {err}");
+                }
+            });
+        }
+    }
+}
+
+/// Removes signals with the same member as an earlier signal,
+/// keeping the order of the first of each.
+///
+/// Properties are fetched fresh for each signal,
+/// so a single fetch covers all of the duplicates.
+/// The last of each is kept in place of the first,
+/// as some signals carry the new value in their body.
+fn dedup_signals(changes: Vec<Message>) -> Vec<Message> {
+    let mut positions = HashMap::new();
+    let mut deduped: Vec<Message> = Vec::with_capacity(changes.len());
+
+    for change in changes {
+        let member = change.header().member().map(ToString::to_string);
+        match positions.entry(member) {
+            Entry::Occupied(entry) => deduped[*entry.get()] = change,
+            Entry::Vacant(entry) => {
+                entry.insert(deduped.len());
+                deduped.push(change);
             }
         }
+    }
 
-        Ok(())
+    deduped
+}
+
+/// Gets the new status carried in the body of a `NewStatus` signal.
+///
+/// The spec includes it, which saves fetching the property,
+/// but some items send the signal without a body.
+fn signal_status(change: &Message) -> Option<item::Status> {
+    let body = change.body();
+    body.deserialize::<&str>().ok().map(item::Status::from)
+}
+
+/// Converts a broadcast receiver into a stream,
+/// skipping events which were missed due to lag.
+fn receiver_stream<T: Clone + Send + 'static>(
+    rx: broadcast::Receiver<T>,
+) -> impl Stream<Item = T> + Send + 'static {
+    futures_lite::stream::unfold(rx, |mut rx| async move {
+        loop {
+            match rx.recv().await {
+                Ok(value) => break Some((value, rx)),
+                Err(broadcast::error::RecvError::Lagged(count)) => {
+                    warn!("event stream skipped {count} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => break None,
+            }
+        }
+    })
+}
+
+/// Sends an [`Event::Heartbeat`] every `interval`
+/// until there are no receivers left.
+async fn send_heartbeats(tx: broadcast::Sender<Event>, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+
+    // the first tick completes immediately
+    ticker.tick().await;
+
+    loop {
+        ticker.tick().await;
+        if tx.send(Event::Heartbeat).is_err() {
+            break;
+        }
+    }
+}
+
+/// Sends the updates held back by `queue` as receivers catch up.
+///
+/// There is no signal for when a receiver reads an event,
+/// so while any are held back this checks for room every [`QUEUE_FLUSH_INTERVAL`].
+async fn flush_queued_events(queue: Arc<EventQueue>, tx: broadcast::Sender<Event>) -> Result<()> {
+    loop {
+        queue.queued().await;
+
+        while !queue.flush(&tx)? {
+            tokio::time::sleep(QUEUE_FLUSH_INTERVAL).await;
+        }
+    }
+}
+
+/// Forwards events from `rx` to `tx`, wrapping each in an [`EventEnvelope`].
+///
+/// If `rx` lags, the sequence number is advanced by the number of missed events.
+async fn sequence_events(mut rx: broadcast::Receiver<Event>, tx: broadcast::Sender<EventEnvelope>) {
+    use broadcast::error::RecvError;
+
+    let mut seq = 0;
+    loop {
+        match rx.recv().await {
+            Ok(event) => {
+                // there may not be any sequenced receivers, which is fine
+                let _ = tx.send(EventEnvelope {
+                    seq,
+                    sent_at: Some(Instant::now()),
+                    event,
+                });
+                seq += 1;
+            }
+            Err(RecvError::Lagged(missed)) => {
+                warn!("event sequencer lagged, skipping {missed} events");
+                seq += missed;
+            }
+            Err(RecvError::Closed) => break,
+        }
     }
 }
 
+/// Checks whether `err` was caused by an item's properties being invalid,
+/// rather than by failing to fetch them.
+fn is_malformed(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::MissingProperty(_) | Error::InvalidData(_) | Error::ZBusVariant(_)
+    )
+}
+
 fn parse_address(address: &str) -> (&str, String) {
     address
         .split_once('/')
@@ -745,6 +3168,7 @@ fn parse_address(address: &str) -> (&str, String) {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tokio::spawn;
 
     #[test]
     fn parse_unnamed() {
@@ -763,4 +3187,287 @@ mod tests {
         assert_eq!(":1.72", destination);
         assert_eq!("/org/ayatana/NotificationItem/dropbox_client_1398", path);
     }
+
+    #[tokio::test]
+    async fn heartbeat_interval() {
+        let interval = Duration::from_millis(20);
+        let (tx, mut rx) = broadcast::channel(8);
+
+        let start = tokio::time::Instant::now();
+        let task = spawn(send_heartbeats(tx, interval));
+
+        for _ in 0..3 {
+            let event = rx.recv().await.expect("heartbeat should be received");
+            assert!(matches!(event, Event::Heartbeat));
+        }
+
+        let elapsed = start.elapsed();
+        assert!(elapsed >= interval * 3, "heartbeats too fast: {elapsed:?}");
+        assert!(elapsed < interval * 20, "heartbeats too slow: {elapsed:?}");
+
+        drop(rx);
+        task.await
+            .expect("heartbeat task should stop without receivers");
+    }
+
+    #[tokio::test]
+    async fn sequence_increasing() {
+        let (tx, rx) = broadcast::channel(8);
+        let (envelope_tx, mut envelope_rx) = broadcast::channel(8);
+
+        let task = spawn(sequence_events(rx, envelope_tx));
+
+        for _ in 0..5 {
+            tx.send(Event::Heartbeat).expect("send should succeed");
+        }
+        drop(tx);
+
+        let mut last = None;
+        let mut last_sent = None;
+        for _ in 0..5 {
+            let envelope = envelope_rx
+                .recv()
+                .await
+                .expect("envelope should be received");
+            assert!(last.is_none_or(|last| envelope.seq > last));
+            assert!(envelope.sent_at.is_some());
+            assert!(envelope.sent_at >= last_sent);
+            last = Some(envelope.seq);
+            last_sent = envelope.sent_at;
+        }
+
+        task.await.expect("sequencer should stop once closed");
+    }
+
+    #[tokio::test]
+    async fn sequence_lagged() {
+        let (tx, rx) = broadcast::channel(2);
+        let (envelope_tx, mut envelope_rx) = broadcast::channel(8);
+
+        // fill the channel before the sequencer runs so that it lags
+        for _ in 0..5 {
+            tx.send(Event::Heartbeat).expect("send should succeed");
+        }
+        drop(tx);
+
+        sequence_events(rx, envelope_tx).await;
+
+        let first = envelope_rx
+            .recv()
+            .await
+            .expect("envelope should be received");
+        let second = envelope_rx
+            .recv()
+            .await
+            .expect("envelope should be received");
+
+        assert_eq!(3, first.seq);
+        assert_eq!(4, second.seq);
+    }
+
+    #[tokio::test]
+    async fn failed_tasks_send_errors() {
+        let (tx, mut rx) = broadcast::channel(4);
+        let tasks = Tasks::new(tx);
+
+        tasks.spawn(async { Err::<(), _>(Error::InvalidData("boom")) });
+
+        let event = rx.recv().await.expect("error should be sent");
+        assert!(matches!(event, Event::Error(ref err) if err.contains("boom")));
+
+        tasks.spawn(async { Ok::<(), Error>(()) });
+        tasks.spawn(async {});
+        tokio::task::yield_now().await;
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn abort_all_stops_tasks() {
+        let tasks = Tasks::new(broadcast::channel(1).0);
+        let (tx, mut rx) = broadcast::channel::<Event>(1);
+
+        tasks.spawn(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await;
+        });
+        tasks.abort_all();
+
+        // the sender is dropped once the task is aborted
+        assert!(rx.recv().await.is_err());
+
+        let (tx, mut rx) = broadcast::channel::<Event>(1);
+        tasks.spawn(async move {
+            let _tx = tx;
+            std::future::pending::<()>().await;
+        });
+
+        // tasks spawned after aborting never run
+        assert!(rx.recv().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn receiver_stream_skips_lagged() {
+        let (tx, rx) = broadcast::channel(2);
+
+        for i in 0..5 {
+            tx.send(i).expect("send should succeed");
+        }
+        drop(tx);
+
+        let values = receiver_stream(rx).collect::<Vec<_>>().await;
+        assert_eq!(vec![3, 4], values);
+    }
+
+    #[tokio::test]
+    async fn receiver_stream_merges() {
+        let (tx, rx) = broadcast::channel(2);
+        let (other_tx, other_rx) = tokio::sync::mpsc::unbounded_channel();
+        let other = futures_lite::stream::unfold(other_rx, |mut rx| async move {
+            rx.recv().await.map(|value| (value, rx))
+        });
+
+        let mut merged = std::pin::pin!(receiver_stream(rx).or(other));
+
+        // both streams are pending when polled,
+        // so each has to wake the merged stream itself
+        spawn(async move {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            other_tx.send(1).expect("send should succeed");
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            tx.send(2).expect("send should succeed");
+        });
+
+        assert_eq!(Some(1), merged.next().await);
+        assert_eq!(Some(2), merged.next().await);
+        assert_eq!(None, merged.next().await);
+    }
+
+    #[cfg(feature = "data")]
+    #[test]
+    fn filter_and() {
+        let passive = EventFilter::new(|item| item.status == item::Status::Passive);
+        let named = EventFilter::new(|item| item.id == "test");
+        let filter = passive.and(named);
+
+        let mut item = StatusNotifierItem {
+            id: "test".to_string(),
+            status: item::Status::Passive,
+            ..Default::default()
+        };
+        assert!(filter.matches(&item));
+
+        item.status = item::Status::Active;
+        assert!(!filter.matches(&item));
+
+        item.status = item::Status::Passive;
+        item.id = "other".to_string();
+        assert!(!filter.matches(&item));
+    }
+
+    #[test]
+    fn activate_request_from_item() {
+        let mut item = StatusNotifierItem::default();
+        assert!(ActivateRequest::menu_item(":1.52", &item, 3).is_none());
+
+        item.menu = Some("/MenuBar".to_string());
+        let req = ActivateRequest::menu_item(":1.52", &item, 3);
+        assert!(matches!(
+            req,
+            Some(ActivateRequest::MenuItem { address, menu_path, submenu_id: 3 })
+                if address == ":1.52" && menu_path == "/MenuBar"
+        ));
+    }
+
+    #[test]
+    fn coordinate_space_to_screen() {
+        assert_eq!(Some((10, 20)), CoordinateSpace::Screen.to_screen(10, 20));
+
+        let space = CoordinateSpace::Logical { scale: 1.5 };
+        assert_eq!(Some((15, 30)), space.to_screen(10, 20));
+        assert_eq!(Some((2, 0)), space.to_screen(1, 0));
+        assert_eq!(Some((-15, -2)), space.to_screen(-10, -1));
+        assert_eq!(None, space.to_screen(i32::MAX, 0));
+        assert_eq!(None, space.to_screen(0, i32::MIN));
+    }
+
+    #[test]
+    fn event_kind() {
+        let update = UpdateEvent::MenuDiff(vec![]);
+        assert_eq!("menu-diff", update.kind());
+
+        let event = Event::Update(":1.52".to_string(), update);
+        assert_eq!("update", event.kind());
+        assert_eq!("remove", Event::Remove(":1.52".to_string()).kind());
+    }
+
+    #[test]
+    fn dedup_signals_by_member() {
+        let signal = |member: &str| {
+            Message::signal("/StatusNotifierItem", "org.kde.StatusNotifierItem", member)
+                .expect("valid signal")
+                .build(&())
+                .expect("valid message")
+        };
+
+        let changes = ["NewIcon", "NewToolTip", "NewIcon", "NewIcon", "NewStatus"]
+            .map(signal)
+            .to_vec();
+
+        let members = dedup_signals(changes)
+            .iter()
+            .map(|change| change.header().member().map(ToString::to_string))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            vec![
+                Some("NewIcon".to_string()),
+                Some("NewToolTip".to_string()),
+                Some("NewStatus".to_string())
+            ],
+            members
+        );
+    }
+
+    #[test]
+    fn status_from_signal_body() {
+        let signal = Message::signal(
+            "/StatusNotifierItem",
+            "org.kde.StatusNotifierItem",
+            "NewStatus",
+        )
+        .expect("valid signal");
+
+        let change = signal.build(&("NeedsAttention",)).expect("valid message");
+        assert_eq!(Some(item::Status::NeedsAttention), signal_status(&change));
+
+        let signal = Message::signal(
+            "/StatusNotifierItem",
+            "org.kde.StatusNotifierItem",
+            "NewStatus",
+        )
+        .expect("valid signal");
+        let change = signal.build(&()).expect("valid message");
+        assert_eq!(None, signal_status(&change));
+    }
+
+    #[test]
+    fn dedup_signals_keeps_last_body() {
+        let signal = |status: &str| {
+            Message::signal(
+                "/StatusNotifierItem",
+                "org.kde.StatusNotifierItem",
+                "NewStatus",
+            )
+            .expect("valid signal")
+            .build(&(status,))
+            .expect("valid message")
+        };
+
+        let changes = dedup_signals(vec![signal("Active"), signal("NeedsAttention")]);
+        assert_eq!(1, changes.len());
+        assert_eq!(
+            Some(item::Status::NeedsAttention),
+            signal_status(&changes[0])
+        );
+    }
 }
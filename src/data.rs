@@ -1,11 +1,17 @@
-use crate::{
-    item::StatusNotifierItem,
-    menu::{MenuDiff, MenuItem, MenuItemUpdate, TrayMenu},
-};
+use crate::client::Event;
+use crate::error::Result;
+use crate::menu::{MenuDiff, TrayMenu};
+use crate::queue::EventQueue;
+use crate::sync::MutexExt;
 use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
 
 #[cfg(feature = "data")]
-use {crate::client::UpdateEvent, tracing::error};
+use {
+    crate::client::{EventFilter, UpdateEvent},
+    crate::item::StatusNotifierItem,
+    tracing::{debug, error},
+};
 
 #[cfg(feature = "data")]
 pub type BaseMap = std::collections::HashMap<String, (StatusNotifierItem, Option<TrayMenu>)>;
@@ -16,12 +22,60 @@ type BaseMap = std::collections::HashSet<String>;
 #[derive(Debug, Clone)]
 pub(crate) struct TrayItemMap {
     inner: Arc<Mutex<BaseMap>>,
+
+    /// Items which do not match the filter.
+    /// These are kept up to date, but no events are sent for them.
+    ///
+    /// This is only ever locked while `inner` is also locked.
+    #[cfg(feature = "data")]
+    hidden: Arc<Mutex<BaseMap>>,
+    /// Items which are expected to be added again,
+    /// with their state at the time.
+    /// See [`TrayItemMap::expect_readd`].
+    ///
+    /// This is only ever locked while `inner` is also locked.
+    #[cfg(feature = "data")]
+    readding: Arc<Mutex<std::collections::HashMap<String, StatusNotifierItem>>>,
+    #[cfg(feature = "data")]
+    filter: Option<EventFilter>,
+    /// Where events are sent through,
+    /// if they are coalesced when a receiver falls behind.
+    queue: Option<Arc<EventQueue>>,
 }
 
 impl TrayItemMap {
     pub(crate) fn new() -> Self {
         Self {
             inner: Arc::new(Mutex::new(BaseMap::default())),
+            #[cfg(feature = "data")]
+            hidden: Arc::default(),
+            #[cfg(feature = "data")]
+            readding: Arc::default(),
+            #[cfg(feature = "data")]
+            filter: None,
+            queue: None,
+        }
+    }
+
+    /// Sends events through `queue` from now on.
+    pub(crate) fn with_queue(mut self, queue: Arc<EventQueue>) -> Self {
+        self.queue = Some(queue);
+        self
+    }
+
+    fn send_event(&self, tx: &broadcast::Sender<Event>, event: Event) -> Result<()> {
+        match &self.queue {
+            Some(queue) => queue.send(tx, event),
+            None => tx.send(event).map(|_| ()).map_err(Into::into),
+        }
+    }
+
+    /// Creates a map which only sends events for items matching `filter`.
+    #[cfg(feature = "data")]
+    pub(crate) fn with_filter(filter: Option<EventFilter>) -> Self {
+        Self {
+            filter,
+            ..Self::new()
         }
     }
 
@@ -30,120 +84,624 @@ impl TrayItemMap {
         self.inner.clone()
     }
 
-    pub(crate) fn new_item(&self, dest: String, item: &StatusNotifierItem) {
-        let mut lock = self.inner.lock().expect("mutex lock should succeed");
+    #[cfg(feature = "data")]
+    fn is_visible(&self, item: &StatusNotifierItem) -> bool {
+        self.filter
+            .as_ref()
+            .is_none_or(|filter| filter.matches(item))
+    }
+
+    /// Applies `event` to the state, then sends it,
+    /// holding the lock throughout.
+    ///
+    /// Holding the lock means a snapshot taken under the same lock
+    /// (see [`TrayItemMap::snapshot_and_subscribe`])
+    /// includes exactly the events sent before it.
+    ///
+    /// Returns `false` without sending if the event does not apply to the state:
+    /// an add for an item which is already tracked,
+    /// or a remove for an item which is not tracked.
+    ///
+    /// Events for items hidden by the filter are applied but not sent.
+    /// If an update changes whether an item is hidden,
+    /// a remove or add event is sent in its place.
+    ///
+    /// An add for an item which is expected to be re-added
+    /// replaces the tracked one.
+    /// If it is the same item, an update is sent for each property which changed
+    /// instead of the add, and `false` is returned as the item is already being watched.
+    /// Otherwise, a remove is sent for the old item before the add,
+    /// and `true` is returned so that the new item is watched in place of the old one.
+    pub(crate) fn send(&self, tx: &broadcast::Sender<Event>, event: Event) -> Result<bool> {
+        let mut lock = self.inner.lock_or_recover();
+
         cfg_if::cfg_if! {
             if #[cfg(feature = "data")] {
-                lock.insert(dest, (item.clone(), None));
-            }else {
-                let _ = item;
-                lock.insert(dest);
+                let mut hidden = self.hidden.lock_or_recover();
+                let mut readding = self.readding.lock_or_recover();
+
+                let mut replaced = None;
+                if let Event::Add { address: dest, item, initial } = &event {
+                    if let Some(old) = readding.remove(dest) {
+                        if is_same_item(&old, item) {
+                            debug!("item re-added: {dest}");
+
+                            let visible = self.is_visible(item);
+                            let events = if let Some(entry) = lock.get_mut(dest) {
+                                let updates = item_updates(&entry.0, item);
+                                entry.0 = item.as_ref().clone();
+
+                                if visible {
+                                    updates
+                                        .into_iter()
+                                        .map(|update| Event::Update(dest.clone(), update))
+                                        .collect()
+                                } else {
+                                    let entry = lock.remove(dest).expect("entry was just found");
+                                    hidden.insert(dest.clone(), entry);
+                                    vec![Event::Remove(dest.clone())]
+                                }
+                            } else if let Some(entry) = hidden.get_mut(dest) {
+                                entry.0 = item.as_ref().clone();
+
+                                if visible {
+                                    let (item, menu) = hidden.remove(dest).expect("entry was just found");
+                                    lock.insert(dest.clone(), (item.clone(), menu.clone()));
+
+                                    let mut events = vec![Event::Add { address: dest.clone(), item: item.into(), initial: *initial }];
+                                    if let Some(menu) = menu {
+                                        events.push(Event::Update(dest.clone(), UpdateEvent::Menu(menu)));
+                                    }
+                                    events
+                                } else {
+                                    vec![]
+                                }
+                            } else {
+                                vec![]
+                            };
+
+                            for event in events {
+                                self.send_event(tx, event)?;
+                            }
+
+                            return Ok(false);
+                        }
+
+                        replaced = lock.remove(dest).map(|_| Event::Remove(dest.clone()));
+                        hidden.remove(dest);
+                    }
+                }
+
+                let (applied, mut events) = match event {
+                    Event::Add { address: dest, item, initial } => {
+                        if lock.contains_key(&dest) || hidden.contains_key(&dest) {
+                            (false, vec![])
+                        } else if self.is_visible(&item) {
+                            lock.insert(dest.clone(), (item.as_ref().clone(), None));
+                            (true, vec![Event::Add { address: dest, item, initial }])
+                        } else {
+                            hidden.insert(dest, (*item, None));
+                            (true, vec![])
+                        }
+                    }
+                    Event::Remove(dest) => {
+                        readding.remove(&dest);
+                        if lock.remove(&dest).is_some() {
+                            (true, vec![Event::Remove(dest)])
+                        } else {
+                            (hidden.remove(&dest).is_some(), vec![])
+                        }
+                    }
+                    Event::Update(dest, update) => {
+                        if let Some(entry) = lock.get_mut(&dest) {
+                            apply_update_event(entry, &update);
+
+                            if self.is_visible(&entry.0) {
+                                (true, vec![Event::Update(dest, update)])
+                            } else {
+                                let entry = lock.remove(&dest).expect("entry was just found");
+                                hidden.insert(dest.clone(), entry);
+                                (true, vec![Event::Remove(dest)])
+                            }
+                        } else if let Some(entry) = hidden.get_mut(&dest) {
+                            apply_update_event(entry, &update);
+
+                            if self.is_visible(&entry.0) {
+                                let (item, menu) = hidden.remove(&dest).expect("entry was just found");
+                                lock.insert(dest.clone(), (item.clone(), menu.clone()));
+
+                                let mut events = vec![Event::Add { address: dest.clone(), item: item.into(), initial: false }];
+                                if let Some(menu) = menu {
+                                    events.push(Event::Update(dest, UpdateEvent::Menu(menu)));
+                                }
+
+                                (true, events)
+                            } else {
+                                (true, vec![])
+                            }
+                        } else {
+                            error!("could not find item in state");
+                            (true, vec![Event::Update(dest, update)])
+                        }
+                    }
+                    event => (true, vec![event]),
+                };
+
+                if let Some(remove) = replaced {
+                    events.insert(0, remove);
+                }
+
+                for event in events {
+                    self.send_event(tx, event)?;
+                }
+            } else {
+                let applied = match &event {
+                    Event::Add { address: dest, .. } => lock.insert(dest.clone()),
+                    Event::Remove(dest) => lock.remove(dest),
+                    _ => true,
+                };
+
+                if applied {
+                    self.send_event(tx, event)?;
+                }
             }
         }
-    }
 
-    pub(crate) fn remove_item(&self, dest: &str) {
-        self.inner
-            .lock()
-            .expect("mutex lock should succeed")
-            .remove(dest);
+        Ok(applied)
     }
 
-    pub(crate) fn clear_items(&self) -> Vec<String> {
-        let mut lock = self.inner.lock().expect("mutex lock should succeed");
+    /// Stops tracking all items,
+    /// sending a remove event for each.
+    pub(crate) fn clear(&self, tx: &broadcast::Sender<Event>) -> Result<()> {
+        let mut lock = self.inner.lock_or_recover();
         cfg_if::cfg_if! {
             if #[cfg(feature = "data")] {
-                lock.drain().map(|(k, _)| k).collect()
-            }else {
-                lock.drain().collect()
+                self.hidden.lock_or_recover().clear();
+                self.readding.lock_or_recover().clear();
+                let dests = lock.drain().map(|(k, _)| k);
+            } else {
+                let dests = lock.drain();
             }
         }
+
+        for dest in dests {
+            self.send_event(tx, Event::Remove(dest))?;
+        }
+
+        Ok(())
     }
 
-    pub(crate) fn update_menu(&self, dest: &str, menu: &TrayMenu) {
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "data")] {
-                if let Some((_, menu_cache)) = self.inner
-                        .lock()
-                        .expect("should get lock")
-                        .get_mut(dest) {
-                    menu_cache.replace(menu.clone());
-                } else {
-                    tracing::error!("could not find item in state");
-                }
-            }else {
-                let _ = menu;
-                let _ = dest;
-            }
+    /// Marks every tracked item as expected to be added again,
+    /// such as when the watcher is taken over and items re-register with it.
+    ///
+    /// The items stay in the state until [`TrayItemMap::remove_unreadded`] is called,
+    /// and no events are sent for those which are re-added by then.
+    #[cfg(feature = "data")]
+    pub(crate) fn expect_readd(&self) {
+        let lock = self.inner.lock_or_recover();
+        let hidden = self.hidden.lock_or_recover();
+
+        *self.readding.lock_or_recover() = lock
+            .iter()
+            .chain(hidden.iter())
+            .map(|(dest, (item, _))| (dest.clone(), item.clone()))
+            .collect();
+    }
+
+    /// Stops tracking the items which were expected to be added again but were not,
+    /// sending a remove event for each.
+    #[cfg(feature = "data")]
+    pub(crate) fn remove_unreadded(&self, tx: &broadcast::Sender<Event>) -> Result<()> {
+        let dests = {
+            let _lock = self.inner.lock_or_recover();
+            std::mem::take(&mut *self.readding.lock_or_recover())
+        };
+
+        for dest in dests.into_keys() {
+            self.send(tx, Event::Remove(dest))?;
         }
+
+        Ok(())
     }
 
+    /// Clones a single item and its menu out of the state.
     #[cfg(feature = "data")]
-    pub(crate) fn apply_update_event(&self, dest: &str, event: &UpdateEvent) {
-        if let Some((item, menu)) = self
-            .inner
-            .lock()
-            .expect("mutex lock should succeed")
-            .get_mut(dest)
-        {
-            match event {
-                UpdateEvent::AttentionIcon(icon_name) => {
-                    item.attention_icon_name.clone_from(icon_name);
-                }
-                UpdateEvent::Icon {
-                    icon_name,
-                    icon_pixmap,
-                } => {
-                    item.icon_name.clone_from(icon_name);
-                    item.icon_pixmap.clone_from(icon_pixmap);
-                }
-                UpdateEvent::OverlayIcon(icon_name) => item.overlay_icon_name.clone_from(icon_name),
-                UpdateEvent::Status(status) => item.status = *status,
-                UpdateEvent::Title(title) => item.title.clone_from(title),
-                UpdateEvent::Tooltip(tooltip) => item.tool_tip.clone_from(tooltip),
-                UpdateEvent::Menu(tray_menu) => *menu = Some(tray_menu.clone()),
-                UpdateEvent::MenuConnect(menu) => item.menu = Some(menu.clone()),
-                UpdateEvent::MenuDiff(menu_diffs) => {
-                    if let Some(menu) = menu {
-                        apply_menu_diffs(menu, menu_diffs);
-                    }
-                }
+    pub(crate) fn get(&self, dest: &str) -> Option<(StatusNotifierItem, Option<TrayMenu>)> {
+        self.inner.lock_or_recover().get(dest).cloned()
+    }
+
+    /// Clones the current state and subscribes to `tx`,
+    /// such that the receiver gets every event sent after the snapshot was taken.
+    #[cfg(feature = "data")]
+    pub(crate) fn snapshot_and_subscribe(
+        &self,
+        tx: &broadcast::Sender<Event>,
+    ) -> (BaseMap, broadcast::Receiver<Event>) {
+        let lock = self.inner.lock_or_recover();
+        (lock.clone(), tx.subscribe())
+    }
+}
+
+#[cfg(feature = "data")]
+fn apply_update_event(
+    (item, menu): &mut (StatusNotifierItem, Option<TrayMenu>),
+    event: &UpdateEvent,
+) {
+    match event {
+        UpdateEvent::AttentionIcon(icon_name) => {
+            item.attention_icon_name.clone_from(icon_name);
+        }
+        UpdateEvent::Icon {
+            icon_name,
+            icon_pixmap,
+        } => {
+            item.icon_name.clone_from(icon_name);
+            item.icon_pixmap.clone_from(icon_pixmap);
+        }
+        UpdateEvent::OverlayIcon(icon_name) => item.overlay_icon_name.clone_from(icon_name),
+        UpdateEvent::Status(status) => item.status = *status,
+        UpdateEvent::Title(title) => item.title.clone_from(title),
+        UpdateEvent::Tooltip(tooltip) => item.tool_tip.clone_from(tooltip),
+        UpdateEvent::Menu(tray_menu) => *menu = Some(tray_menu.clone()),
+        UpdateEvent::MenuConnect(menu_path) => {
+            item.menu = Some(menu_path.clone());
+            // the cached layout belongs to the previous menu, if any
+            *menu = None;
+        }
+        UpdateEvent::MenuDisconnect => {
+            item.menu = None;
+            *menu = None;
+        }
+        UpdateEvent::ItemIsMenu(item_is_menu) => item.item_is_menu = *item_is_menu,
+        // nothing to cache for properties the item type does not know about
+        UpdateEvent::Other { .. } => {}
+        UpdateEvent::MenuDiff(menu_diffs) => {
+            if let Some(menu) = menu {
+                apply_menu_diffs(menu, menu_diffs);
             }
-        } else {
-            error!("could not find item in state");
         }
+        UpdateEvent::MenuTextDirection(text_direction) => {
+            if let Some(menu) = menu {
+                menu.text_direction = *text_direction;
+            }
+        }
+        UpdateEvent::MenuStatus(status) => {
+            if let Some(menu) = menu {
+                menu.status = *status;
+            }
+        }
+        UpdateEvent::MenuSubtree {
+            parent_id,
+            menu: subtree,
+        } => {
+            if let Some(menu) = menu {
+                menu.apply_subtree(*parent_id, subtree.clone());
+            }
+        }
+    }
+}
+
+/// Checks whether two items are the same logical item,
+/// going by their id, category and title.
+#[cfg(feature = "data")]
+fn is_same_item(a: &StatusNotifierItem, b: &StatusNotifierItem) -> bool {
+    a.id == b.id && a.category == b.category && a.title == b.title
+}
+
+/// Gets the update events which change `old` into `new`,
+/// for the properties which have one.
+#[cfg(feature = "data")]
+fn item_updates(old: &StatusNotifierItem, new: &StatusNotifierItem) -> Vec<UpdateEvent> {
+    let mut updates = vec![];
+
+    if old.icon_name != new.icon_name || old.icon_pixmap != new.icon_pixmap {
+        updates.push(UpdateEvent::Icon {
+            icon_name: new.icon_name.clone(),
+            icon_pixmap: new.icon_pixmap.clone(),
+        });
+    }
+    if old.attention_icon_name != new.attention_icon_name {
+        updates.push(UpdateEvent::AttentionIcon(new.attention_icon_name.clone()));
+    }
+    if old.overlay_icon_name != new.overlay_icon_name {
+        updates.push(UpdateEvent::OverlayIcon(new.overlay_icon_name.clone()));
     }
+    if old.status != new.status {
+        updates.push(UpdateEvent::Status(new.status));
+    }
+    if old.title != new.title {
+        updates.push(UpdateEvent::Title(new.title.clone()));
+    }
+    if old.tool_tip != new.tool_tip {
+        updates.push(UpdateEvent::Tooltip(new.tool_tip.clone()));
+    }
+    if old.item_is_menu != new.item_is_menu {
+        updates.push(UpdateEvent::ItemIsMenu(new.item_is_menu));
+    }
+
+    updates
 }
 
+/// Applies each diff to the item with the matching id,
+/// searching the whole menu tree.
+///
+/// See [`TrayMenu::apply_diff`].
 pub fn apply_menu_diffs(tray_menu: &mut TrayMenu, diffs: &[MenuDiff]) {
-    let mut diff_iter = diffs.iter().peekable();
-    tray_menu.submenus.iter_mut().for_each(|item| {
-        if let Some(diff) = diff_iter.next_if(|d| d.id == item.id) {
-            apply_menu_item_diff(item, &diff.update);
-        }
-    });
+    tray_menu.apply_diff(diffs);
 }
 
-fn apply_menu_item_diff(menu_item: &mut MenuItem, update: &MenuItemUpdate) {
-    if let Some(label) = &update.label {
-        menu_item.label.clone_from(label);
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use super::*;
+    use crate::dbus::DBusProps;
+    use crate::menu::{MenuItem, MenuItemUpdate};
+    use std::collections::{HashMap, HashSet};
+    use zbus::zvariant::{OwnedValue, Value};
+
+    fn item(id: &str) -> StatusNotifierItem {
+        let id = OwnedValue::try_from(Value::from(id)).expect("owned value");
+        StatusNotifierItem::try_from(DBusProps(HashMap::from([("Id".to_string(), id)])))
+            .expect("valid item")
     }
-    if let Some(enabled) = update.enabled {
-        menu_item.enabled = enabled;
+
+    #[test]
+    fn snapshot_and_subscribe_is_atomic() {
+        const COUNT: usize = 200;
+
+        let items = TrayItemMap::new();
+        let (tx, _rx) = broadcast::channel(COUNT);
+
+        let sender = std::thread::spawn({
+            let items = items.clone();
+            let tx = tx.clone();
+            move || {
+                for i in 0..COUNT {
+                    let event = Event::Add {
+                        address: format!(":1.{i}"),
+                        item: Box::new(item("test")),
+                        initial: false,
+                    };
+                    items.send(&tx, event).expect("send should succeed");
+                }
+            }
+        });
+
+        let (snapshot, mut rx) = items.snapshot_and_subscribe(&tx);
+        sender.join().expect("sender should not panic");
+
+        let mut seen = snapshot.into_keys().collect::<HashSet<_>>();
+        while let Ok(event) = rx.try_recv() {
+            let Event::Add { address: dest, .. } = event else {
+                panic!("unexpected event: {event:?}");
+            };
+            assert!(seen.insert(dest), "event was already in snapshot");
+        }
+
+        assert_eq!(COUNT, seen.len());
     }
-    if let Some(visible) = update.visible {
-        menu_item.visible = visible;
+
+    #[test]
+    fn duplicate_add_and_remove_are_not_sent() {
+        let items = TrayItemMap::new();
+        let (tx, mut rx) = broadcast::channel(8);
+
+        let add = || Event::Add {
+            address: ":1.1".to_string(),
+            item: Box::new(item("test")),
+            initial: false,
+        };
+        assert!(items.send(&tx, add()).expect("send should succeed"));
+        assert!(!items.send(&tx, add()).expect("send should succeed"));
+
+        let remove = || Event::Remove(":1.1".to_string());
+        assert!(items.send(&tx, remove()).expect("send should succeed"));
+        assert!(!items.send(&tx, remove()).expect("send should succeed"));
+
+        assert!(matches!(rx.try_recv(), Ok(Event::Add { .. })));
+        assert!(matches!(rx.try_recv(), Ok(Event::Remove(..))));
+        assert!(rx.try_recv().is_err());
     }
-    if let Some(icon_name) = &update.icon_name {
-        menu_item.icon_name.clone_from(icon_name);
+
+    #[test]
+    fn menu_diff_updates_cache() {
+        let items = TrayItemMap::new();
+        let (tx, _rx) = broadcast::channel(8);
+
+        let menu = TrayMenu {
+            revision: 1,
+            submenus: vec![
+                MenuItem {
+                    id: 1,
+                    label: Some("Open".to_string()),
+                    ..Default::default()
+                },
+                MenuItem {
+                    id: 2,
+                    label: Some("Recent".to_string()),
+                    submenu: vec![MenuItem {
+                        id: 3,
+                        label: Some("notes.txt".to_string()),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let diff = |id: i32, label: &str| MenuDiff {
+            id,
+            update: MenuItemUpdate {
+                label: Some(Some(label.to_string())),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        let dest = ":1.1".to_string();
+        let events = [
+            Event::Add {
+                address: dest.clone(),
+                item: Box::new(item("test")),
+                initial: false,
+            },
+            Event::Update(dest.clone(), UpdateEvent::Menu(menu)),
+            Event::Update(
+                dest.clone(),
+                UpdateEvent::MenuDiff(vec![diff(3, "todo.txt"), diff(1, "Open...")]),
+            ),
+        ];
+
+        for event in events {
+            items.send(&tx, event).expect("send should succeed");
+        }
+
+        let map = items.get_map();
+        let map = map.lock().expect("mutex lock should succeed");
+        let menu = map[&dest].1.as_ref().expect("menu should be cached");
+
+        assert_eq!(Some("Open..."), menu.submenus[0].label.as_deref());
+        assert_eq!(Some("Recent"), menu.submenus[1].label.as_deref());
+        assert_eq!(
+            Some("todo.txt"),
+            menu.submenus[1].submenu[0].label.as_deref()
+        );
     }
-    if let Some(icon_data) = &update.icon_data {
-        menu_item.icon_data.clone_from(icon_data);
+
+    #[test]
+    fn filter_hides_and_reveals_items() {
+        use crate::client::EventFilter;
+        use crate::item::Status;
+
+        let filter = EventFilter::new(|item| item.status != Status::Passive);
+        let items = TrayItemMap::with_filter(Some(filter));
+        let (tx, mut rx) = broadcast::channel(8);
+
+        let dest = ":1.1".to_string();
+        let status = |status| Event::Update(dest.clone(), UpdateEvent::Status(status));
+
+        let mut passive = item("test");
+        passive.status = Status::Passive;
+
+        assert!(items
+            .send(
+                &tx,
+                Event::Add {
+                    address: dest.clone(),
+                    item: Box::new(passive),
+                    initial: false,
+                }
+            )
+            .expect("send should succeed"));
+        assert!(rx.try_recv().is_err());
+        assert!(items.get(&dest).is_none());
+
+        items
+            .send(&tx, status(Status::Active))
+            .expect("send should succeed");
+        assert!(matches!(rx.try_recv(), Ok(Event::Add { .. })));
+        assert!(items.get(&dest).is_some());
+
+        items
+            .send(&tx, status(Status::Passive))
+            .expect("send should succeed");
+        assert!(matches!(rx.try_recv(), Ok(Event::Remove(..))));
+
+        assert!(items
+            .send(&tx, Event::Remove(dest.clone()))
+            .expect("send should succeed"));
+        assert!(rx.try_recv().is_err());
     }
-    if let Some(toggle_state) = update.toggle_state {
-        menu_item.toggle_state = toggle_state;
+
+    #[test]
+    fn readded_items_are_coalesced() {
+        let items = TrayItemMap::new();
+        let (tx, mut rx) = broadcast::channel(8);
+
+        let add = |dest: &str, id: &str| Event::Add {
+            address: dest.to_string(),
+            item: Box::new(item(id)),
+            initial: false,
+        };
+
+        for event in [
+            add(":1.1", "kept"),
+            add(":1.2", "gone"),
+            add(":1.3", "changed"),
+        ] {
+            items.send(&tx, event).expect("send should succeed");
+        }
+        while rx.try_recv().is_ok() {}
+
+        items.expect_readd();
+
+        // the same item with a changed property is updated in place
+        let mut kept = item("kept");
+        kept.status = crate::item::Status::NeedsAttention;
+        assert!(!items
+            .send(
+                &tx,
+                Event::Add {
+                    address: ":1.1".to_string(),
+                    item: Box::new(kept),
+                    initial: false,
+                }
+            )
+            .expect("send should succeed"));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Event::Update(dest, UpdateEvent::Status(crate::item::Status::NeedsAttention)))
+                if dest == ":1.1"
+        ));
+        assert!(rx.try_recv().is_err());
+
+        // a different item is watched in place of the old one
+        assert!(items
+            .send(&tx, add(":1.3", "other"))
+            .expect("send should succeed"));
+        assert!(matches!(rx.try_recv(), Ok(Event::Remove(dest)) if dest == ":1.3"));
+        assert!(
+            matches!(rx.try_recv(), Ok(Event::Add { address, item, .. }) if address == ":1.3" && item.id == "other")
+        );
+
+        items.remove_unreadded(&tx).expect("send should succeed");
+        assert!(matches!(rx.try_recv(), Ok(Event::Remove(dest)) if dest == ":1.2"));
+        assert!(rx.try_recv().is_err());
+
+        let (kept, _) = items.get(":1.1").expect("item to be kept");
+        assert_eq!(crate::item::Status::NeedsAttention, kept.status);
+        assert!(items.get(":1.2").is_none());
     }
-    if let Some(disposition) = update.disposition {
-        menu_item.disposition = disposition;
+
+    #[test]
+    fn menu_disconnect_clears_menu() {
+        let items = TrayItemMap::new();
+        let (tx, _rx) = broadcast::channel(8);
+
+        let dest = ":1.1".to_string();
+        let menu = TrayMenu {
+            revision: 1,
+            submenus: vec![],
+            ..Default::default()
+        };
+
+        let events = [
+            Event::Add {
+                address: dest.clone(),
+                item: Box::new(item("test")),
+                initial: false,
+            },
+            Event::Update(dest.clone(), UpdateEvent::MenuConnect("/Menu".to_string())),
+            Event::Update(dest.clone(), UpdateEvent::Menu(menu)),
+            Event::Update(dest.clone(), UpdateEvent::MenuDisconnect),
+        ];
+
+        for event in events {
+            items.send(&tx, event).expect("send should succeed");
+        }
+
+        let (item, menu) = items.get(&dest).expect("item should be tracked");
+        assert!(item.menu.is_none());
+        assert!(menu.is_none());
     }
 }
@@ -31,8 +31,7 @@ pub(crate) struct SubMenuLayout {
     pub submenus: Vec<OwnedValue>,
 }
 
-#[allow(dead_code)]
-type GroupProperties = Vec<(i32, HashMap<String, zbus::zvariant::OwnedValue>)>;
+pub(crate) type GroupProperties = Vec<(i32, HashMap<String, zbus::zvariant::OwnedValue>)>;
 
 #[derive(Deserialize, Type, Debug, Clone)]
 pub struct PropertiesUpdate<'a> {
@@ -59,6 +58,8 @@ pub struct RemovedProps<'a> {
 pub trait DBusMenu {
     fn about_to_show(&self, id: i32) -> zbus::Result<bool>;
 
+    fn about_to_show_group(&self, ids: &[i32]) -> zbus::Result<(Vec<i32>, Vec<i32>)>;
+
     fn event(
         &self,
         id: i32,
@@ -67,11 +68,13 @@ pub trait DBusMenu {
         timestamp: u32,
     ) -> zbus::Result<()>;
 
+    fn event_group(&self, events: &[(i32, &str, Value<'_>, u32)]) -> zbus::Result<Vec<i32>>;
+
     fn get_group_properties(
         &self,
         ids: &[i32],
         property_names: &[&str],
-    ) -> zbus::Result<(u32, GroupProperties)>;
+    ) -> zbus::Result<GroupProperties>;
 
     fn get_layout(
         &self,
@@ -95,9 +98,15 @@ pub trait DBusMenu {
     #[zbus(signal)]
     fn layout_updated(&self, revision: u32, parent: i32) -> zbus::Result<()>;
 
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> zbus::Result<Vec<String>>;
+
     #[zbus(property)]
     fn status(&self) -> zbus::Result<String>;
 
+    #[zbus(property)]
+    fn text_direction(&self) -> zbus::Result<String>;
+
     #[zbus(property)]
     fn version(&self) -> zbus::Result<u32>;
 }
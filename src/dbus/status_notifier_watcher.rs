@@ -1,4 +1,5 @@
 use crate::names;
+use crate::sync::MutexExt;
 use std::borrow::Cow;
 use std::collections::HashSet;
 use std::sync::{Arc, Mutex};
@@ -47,7 +48,7 @@ impl StatusNotifierWatcher {
 
         let added_first = {
             // scoped around locking of hosts
-            let mut hosts = self.hosts.lock().expect("mutex lock should succeed");
+            let mut hosts = self.hosts.lock_or_recover();
             if !hosts.insert(service.to_string()) {
                 // we're already tracking them
                 return Ok(());
@@ -72,7 +73,7 @@ impl StatusNotifierWatcher {
                 info!("lost host: {}", service);
 
                 let removed_last = {
-                    let mut hosts = hosts.lock().expect("mutex lock should succeed");
+                    let mut hosts = hosts.lock_or_recover();
                     let did_remove = hosts.remove(service.as_str());
                     did_remove && hosts.is_empty()
                 };
@@ -113,7 +114,7 @@ impl StatusNotifierWatcher {
     /// IsStatusNotifierHostRegistered property
     #[zbus(property)]
     fn is_status_notifier_host_registered(&self) -> bool {
-        let hosts = self.hosts.lock().expect("mutex lock should succeed");
+        let hosts = self.hosts.lock_or_recover();
         !hosts.is_empty()
     }
 
@@ -131,7 +132,7 @@ impl StatusNotifierWatcher {
         let item = format!("{service}{objpath}");
 
         {
-            let mut items = self.items.lock().expect("mutex lock should succeed");
+            let mut items = self.items.lock_or_recover();
             if !items.insert(item.clone()) {
                 // we're already tracking them
                 info!("new item: {} (duplicate)", item);
@@ -156,7 +157,7 @@ impl StatusNotifierWatcher {
                 debug!("gone item: {}", &item);
 
                 {
-                    let mut items = items.lock().expect("mutex lock should succeed");
+                    let mut items = items.lock_or_recover();
                     items.remove(&item);
                 }
 
@@ -194,10 +195,7 @@ impl StatusNotifierWatcher {
 
         let item = format!("{service}{objpath}");
 
-        self.items
-            .lock()
-            .expect("mutex lock should succeed")
-            .remove(&item);
+        self.items.lock_or_recover().remove(&item);
 
         if let Err(err) = Self::status_notifier_item_unregistered(&context, &item).await {
             error!("{err:?}");
@@ -223,7 +221,7 @@ impl StatusNotifierWatcher {
     /// RegisteredStatusNotifierItems property
     #[zbus(property)]
     fn registered_status_notifier_items(&self) -> Vec<String> {
-        let items = self.items.lock().expect("mutex lock should succeed");
+        let items = self.items.lock_or_recover();
         items.iter().cloned().collect()
     }
 
@@ -6,16 +6,39 @@ pub type Result<T> = std::result::Result<T, Error>;
 
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("dbus properties missing one or more required fields")]
+    #[error("dbus properties missing required field '{0}'")]
     MissingProperty(&'static str),
     #[error("failed to send event through tokio broadcast channel")]
     EventSend(#[from] SendError<Event>),
-    #[error("zbus error")]
+    #[error("zbus error: {0}")]
     ZBus(#[from] zbus::Error),
-    #[error("zbus fdo error")]
+    #[error("zbus fdo error: {0}")]
     ZBusFdo(#[from] zbus::fdo::Error),
-    #[error("zbus variant error")]
+    #[error("zbus variant error: {0}")]
     ZBusVariant(#[from] zbus::zvariant::Error),
-    #[error("invalid data error")]
+    #[error("invalid data error: {0}")]
     InvalidData(&'static str),
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("item '{0}' went away before the request completed")]
+    ItemGone(String),
+    #[error("timed out waiting for an item with id '{0}'")]
+    ItemTimeout(String),
+    #[error("activation position ({x}, {y}) for item '{address}' is out of range once translated to screen coordinates")]
+    InvalidCoordinates { address: String, x: i32, y: i32 },
+    #[error("item '{address}' does not support {method}")]
+    Unsupported {
+        address: String,
+        method: &'static str,
+    },
+    #[error("failed to fetch properties of item '{destination}': {source}")]
+    ItemProperties {
+        destination: String,
+        source: zbus::fdo::Error,
+    },
+    #[error("failed to fetch menu layout from '{destination}': {source}")]
+    MenuLayout {
+        destination: String,
+        source: zbus::Error,
+    },
 }
@@ -0,0 +1,188 @@
+use crate::item::{IconPixmap, StatusNotifierItem};
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// File extensions searched for icons, in order of preference.
+const EXTENSIONS: [&str; 3] = ["png", "svg", "xpm"];
+
+/// Icon theme directories which trays commonly use.
+const CONTEXTS: [&str; 2] = ["apps", "status"];
+
+/// An icon ready to be displayed.
+#[derive(Debug, Clone)]
+pub enum ResolvedIcon {
+    /// An icon file found on disk.
+    Path(PathBuf),
+    /// Raw pixmap data provided by the item.
+    ///
+    /// Pixels are ARGB32 in network byte order.
+    Pixmap(IconPixmap),
+}
+
+impl StatusNotifierItem {
+    /// Resolves the item's icon for display at `size` pixels.
+    ///
+    /// The icon name is looked up in the following order:
+    ///
+    /// - As an absolute path.
+    /// - In the item's `icon_theme_path`.
+    /// - In the `hicolor` theme and `pixmaps` directory
+    ///   of each of `XDG_DATA_HOME` and `XDG_DATA_DIRS`.
+    ///
+    /// If the name is not found, or the item has no icon name,
+    /// the item's pixmap is used instead,
+    /// picked using [`IconPixmap::best_fit`].
+    ///
+    /// Full icon theme support (inheritance, the user's theme, etc.)
+    /// is out of scope; use a dedicated crate if that is needed.
+    #[must_use]
+    pub fn resolve_icon(&self, size: u32) -> Option<ResolvedIcon> {
+        self.icon_name()
+            .and_then(|name| find_icon(name, self.icon_theme_path.as_deref(), size))
+            .map(ResolvedIcon::Path)
+            .or_else(|| {
+                self.icon_pixmap
+                    .as_deref()
+                    .and_then(|pixmaps| IconPixmap::best_fit(pixmaps, size))
+                    .cloned()
+                    .map(ResolvedIcon::Pixmap)
+            })
+    }
+}
+
+/// Finds the file for the icon `name` at `size` pixels.
+fn find_icon(name: &str, theme_path: Option<&str>, size: u32) -> Option<PathBuf> {
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return path.is_file().then(|| path.to_path_buf());
+    }
+
+    let theme_path = theme_path
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from);
+
+    let data_dirs = data_dirs();
+
+    let search_dirs = theme_path
+        .iter()
+        .flat_map(|path| {
+            let mut dirs = vec![path.clone()];
+            dirs.extend(theme_dirs(&path.join("hicolor"), size));
+            dirs
+        })
+        .chain(
+            data_dirs
+                .iter()
+                .flat_map(|dir| theme_dirs(&dir.join("icons").join("hicolor"), size)),
+        )
+        .chain(data_dirs.iter().map(|dir| dir.join("pixmaps")));
+
+    for dir in search_dirs {
+        for extension in EXTENSIONS {
+            let path = dir.join(format!("{name}.{extension}"));
+            if path.is_file() {
+                return Some(path);
+            }
+        }
+    }
+
+    None
+}
+
+/// Gets the directories within the theme at `theme` to search for an icon at `size` pixels.
+fn theme_dirs(theme: &Path, size: u32) -> Vec<PathBuf> {
+    let sizes = [format!("{size}x{size}"), "scalable".to_string()];
+
+    sizes
+        .iter()
+        .flat_map(|size| CONTEXTS.map(|context| theme.join(size).join(context)))
+        .collect()
+}
+
+/// Gets the XDG data directories, in order of preference.
+fn data_dirs() -> Vec<PathBuf> {
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")));
+
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".to_string());
+
+    data_home
+        .into_iter()
+        .chain(env::split_paths(&data_dirs))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus::DBusProps;
+    use std::collections::HashMap;
+    use zbus::zvariant::{OwnedValue, Value};
+
+    fn item(icon_name: &str, theme_path: &Path) -> StatusNotifierItem {
+        let props = [
+            ("Id", Value::from("test")),
+            ("IconName", Value::from(icon_name)),
+            (
+                "IconThemePath",
+                Value::from(theme_path.to_string_lossy().as_ref()),
+            ),
+        ]
+        .into_iter()
+        .map(|(key, value)| {
+            let value = OwnedValue::try_from(value).expect("owned value");
+            (key.to_string(), value)
+        })
+        .collect::<HashMap<_, _>>();
+
+        StatusNotifierItem::try_from(DBusProps(props)).expect("valid item")
+    }
+
+    fn theme_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("system-tray-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).expect("create dir");
+        dir
+    }
+
+    #[test]
+    fn resolve_from_theme_path() {
+        let dir = theme_dir("theme-path");
+        let sized = dir.join("hicolor/22x22/apps");
+        std::fs::create_dir_all(&sized).expect("create dir");
+        std::fs::write(sized.join("app.png"), []).expect("write icon");
+
+        let icon = item("app", &dir).resolve_icon(22);
+        assert!(matches!(icon, Some(ResolvedIcon::Path(path)) if path == sized.join("app.png")));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+
+    #[test]
+    fn resolve_missing_falls_back_to_pixmap() {
+        let dir = theme_dir("missing");
+
+        let mut item = item("system-tray-missing-icon", &dir);
+        item.icon_pixmap = Some(vec![
+            IconPixmap {
+                width: 16,
+                height: 16,
+                pixels: vec![],
+            },
+            IconPixmap {
+                width: 32,
+                height: 32,
+                pixels: vec![],
+            },
+        ]);
+
+        let icon = item.resolve_icon(22);
+        assert!(matches!(icon, Some(ResolvedIcon::Pixmap(pixmap)) if pixmap.width == 32));
+
+        std::fs::remove_dir_all(dir).expect("remove dir");
+    }
+}
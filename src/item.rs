@@ -1,12 +1,13 @@
 use crate::dbus::DBusProps;
 use crate::error::{Error, Result};
 use serde::Deserialize;
-use std::fmt::{Debug, Formatter};
+use std::fmt::{Debug, Display, Formatter};
 use zbus::zvariant::{Array, Structure};
 
 /// Represents an item to display inside the tray.
 /// <https://www.freedesktop.org/wiki/Specifications/StatusNotifierItem/StatusNotifierItem/>
-#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Deserialize, Debug, Clone, Default)]
 pub struct StatusNotifierItem {
     /// A name that should be unique for this application and consistent between sessions, such as the application name itself.
     pub id: String,
@@ -24,6 +25,8 @@ pub struct StatusNotifierItem {
     pub category: Category,
 
     /// A name that describes the application, it can be more descriptive than Id.
+    ///
+    /// An explicitly empty title is `Some("")`, whereas `None` means the item has no title.
     pub title: Option<String>,
 
     /// Describes the status of this item or of the associated application.
@@ -82,13 +85,82 @@ pub struct StatusNotifierItem {
     pub tool_tip: Option<Tooltip>,
 
     /// The item only support the context menu, the visualization should prefer showing the menu or sending `ContextMenu()` instead of `Activate()`
+    ///
+    /// This has no change signal, so it is re-read whenever any other property changes.
     pub item_is_menu: bool,
 
     /// `DBus` path to an object which should implement the `com.canonical.dbusmenu` interface
     pub menu: Option<String>,
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Default)]
+impl StatusNotifierItem {
+    /// Creates an item with only an id,
+    /// for items whose properties cannot be fetched.
+    ///
+    /// The status is [`Status::Unknown`], which real items do not use.
+    pub(crate) fn placeholder(id: String) -> Self {
+        Self {
+            id,
+            ..Default::default()
+        }
+    }
+
+    /// The Freedesktop-compliant name of the main icon, if set.
+    #[must_use]
+    pub fn icon_name(&self) -> Option<&str> {
+        self.icon_name.as_deref()
+    }
+
+    /// The Freedesktop-compliant name of the overlay icon, if set.
+    ///
+    /// This is intended to be drawn on top of the main icon.
+    #[must_use]
+    pub fn overlay_icon_name(&self) -> Option<&str> {
+        self.overlay_icon_name.as_deref()
+    }
+
+    /// The Freedesktop-compliant name of the icon to show
+    /// while the item has the [`Status::NeedsAttention`] status, if set.
+    ///
+    /// This is not resolved against the main icon;
+    /// it is up to the visualization to decide when to fall back.
+    #[must_use]
+    pub fn attention_icon_name(&self) -> Option<&str> {
+        self.attention_icon_name.as_deref()
+    }
+
+    /// How the item expects to be interacted with,
+    /// based on [`StatusNotifierItem::item_is_menu`]
+    /// and whether it has a [`StatusNotifierItem::menu`].
+    ///
+    /// Visualizations can use this to decide what a primary click does.
+    #[must_use]
+    pub fn interaction_mode(&self) -> InteractionMode {
+        match (self.menu.is_some(), self.item_is_menu) {
+            (false, _) => InteractionMode::Activate,
+            (true, true) => InteractionMode::Menu,
+            (true, false) => InteractionMode::Both,
+        }
+    }
+}
+
+/// How an item expects to be interacted with.
+///
+/// See [`StatusNotifierItem::interaction_mode`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InteractionMode {
+    /// The item has no menu, so a primary click should activate it.
+    Activate,
+    /// The item only supports its menu, so a primary click should show it.
+    Menu,
+    /// The item has a menu, which is usually shown on a secondary click,
+    /// and a primary click should activate it.
+    Both,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, Copy, Deserialize, Default, PartialEq, Eq)]
 pub enum Category {
     #[default]
     ApplicationStatus,
@@ -108,7 +180,27 @@ impl From<&str> for Category {
     }
 }
 
-#[derive(Debug, Clone, Copy, Deserialize, Default)]
+impl Category {
+    /// Gets the value of the `Category` property for this category.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::ApplicationStatus => "ApplicationStatus",
+            Self::Communications => "Communications",
+            Self::SystemServices => "SystemServices",
+            Self::Hardware => "Hardware",
+        }
+    }
+}
+
+impl Display for Category {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, Copy, Deserialize, Default, PartialEq, Eq)]
 pub enum Status {
     #[default]
     Unknown,
@@ -128,10 +220,34 @@ impl From<&str> for Status {
     }
 }
 
-#[derive(Deserialize, Clone)]
+impl Status {
+    /// Gets the value of the `Status` property for this status.
+    ///
+    /// [`Status::Unknown`] is not part of the specification,
+    /// and gives `Unknown`.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Unknown => "Unknown",
+            Self::Passive => "Passive",
+            Self::Active => "Active",
+            Self::NeedsAttention => "NeedsAttention",
+        }
+    }
+}
+
+impl Display for Status {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Deserialize, Clone, PartialEq, Eq)]
 pub struct IconPixmap {
     pub width: i32,
     pub height: i32,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64"))]
     pub pixels: Vec<u8>,
 }
 
@@ -188,11 +304,28 @@ impl IconPixmap {
             })
             .collect()
     }
+
+    /// Picks the pixmap to display at `size` pixels.
+    ///
+    /// This is the smallest pixmap at least `size` pixels wide,
+    /// so it only ever needs scaling down.
+    /// If every pixmap is smaller, the largest is picked instead.
+    #[must_use]
+    pub fn best_fit(pixmaps: &[Self], size: u32) -> Option<&Self> {
+        let size = i32::try_from(size).unwrap_or(i32::MAX);
+
+        pixmaps
+            .iter()
+            .filter(|pixmap| pixmap.width >= size)
+            .min_by_key(|pixmap| pixmap.width)
+            .or_else(|| pixmaps.iter().max_by_key(|pixmap| pixmap.width))
+    }
 }
 
 /// Data structure that describes extra information associated to this item, that can be visualized for instance by a tooltip
 /// (or by any other mean the visualization consider appropriate.
-#[derive(Debug, Clone, Deserialize)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
 pub struct Tooltip {
     pub icon_name: String,
     pub icon_data: Vec<IconPixmap>,
@@ -235,6 +368,85 @@ impl TryFrom<&Structure<'_>> for Tooltip {
     }
 }
 
+impl Tooltip {
+    /// Gets the description exactly as sent by the item,
+    /// which may contain markup.
+    #[must_use]
+    pub fn raw(&self) -> &str {
+        &self.description
+    }
+
+    /// Gets the description with markup tags removed and entities decoded,
+    /// for display in places which cannot render markup.
+    ///
+    /// Line break tags (`<br>`) are replaced with newlines.
+    #[must_use]
+    pub fn plain_text(&self) -> String {
+        strip_markup(&self.description)
+    }
+}
+
+/// Removes the tags from `markup` and decodes any entities.
+fn strip_markup(markup: &str) -> String {
+    let mut text = String::with_capacity(markup.len());
+    let mut rest = markup;
+
+    while let Some(index) = rest.find(['<', '&']) {
+        text.push_str(&rest[..index]);
+        rest = &rest[index..];
+
+        if rest.starts_with('<') {
+            let Some(end) = rest.find('>') else {
+                // unterminated tag, treat as text
+                break;
+            };
+
+            let tag = rest[1..end].trim().trim_end_matches('/').trim();
+            if tag.eq_ignore_ascii_case("br") {
+                text.push('\n');
+            }
+
+            rest = &rest[end + 1..];
+        } else {
+            let decoded = rest
+                .find(';')
+                .and_then(|end| decode_entity(&rest[1..end]).map(|c| (c, end)));
+
+            if let Some((c, end)) = decoded {
+                text.push(c);
+                rest = &rest[end + 1..];
+            } else {
+                text.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    text.push_str(rest);
+    text
+}
+
+/// Decodes a single entity, without the leading `&` and trailing `;`.
+fn decode_entity(entity: &str) -> Option<char> {
+    match entity {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let code = entity.strip_prefix('#')?;
+            let code = match code.strip_prefix(['x', 'X']) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => code.parse().ok()?,
+            };
+
+            char::from_u32(code)
+        }
+    }
+}
+
 impl TryFrom<DBusProps> for StatusNotifierItem {
     type Error = Error;
 
@@ -300,3 +512,155 @@ impl DBusProps {
             .map(|t| t.and_then(Tooltip::try_from))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use zbus::zvariant::{OwnedValue, Value};
+
+    fn props(props: Vec<(&str, Value<'static>)>) -> DBusProps {
+        DBusProps(
+            props
+                .into_iter()
+                .map(|(key, value)| {
+                    (
+                        key.to_string(),
+                        OwnedValue::try_from(value).expect("owned value"),
+                    )
+                })
+                .collect::<HashMap<_, _>>(),
+        )
+    }
+
+    #[test]
+    fn parse_icon_names() {
+        let item = StatusNotifierItem::try_from(props(vec![
+            ("Id", Value::from("nm-applet")),
+            ("IconName", Value::from("network-wireless")),
+            ("OverlayIconName", Value::from("emblem-important")),
+            ("AttentionIconName", Value::from("network-error")),
+        ]))
+        .expect("valid item");
+
+        assert_eq!(Some("network-wireless"), item.icon_name());
+        assert_eq!(Some("emblem-important"), item.overlay_icon_name());
+        assert_eq!(Some("network-error"), item.attention_icon_name());
+    }
+
+    #[test]
+    fn parse_missing_icon_names() {
+        let item = StatusNotifierItem::try_from(props(vec![
+            ("Id", Value::from("nm-applet")),
+            ("IconName", Value::from("network-wireless")),
+        ]))
+        .expect("valid item");
+
+        assert_eq!(Some("network-wireless"), item.icon_name());
+        assert_eq!(None, item.overlay_icon_name());
+        assert_eq!(None, item.attention_icon_name());
+    }
+
+    #[test]
+    fn parse_identity() {
+        let item = StatusNotifierItem::try_from(props(vec![
+            ("Id", Value::from("discord")),
+            ("Category", Value::from("Communications")),
+            ("WindowId", Value::from(42)),
+            ("Status", Value::from("NeedsAttention")),
+        ]))
+        .expect("valid item");
+
+        assert_eq!("discord", item.id);
+        assert_eq!(Category::Communications, item.category);
+        assert_eq!(42, item.window_id);
+        assert_eq!(Status::NeedsAttention, item.status);
+    }
+
+    #[test]
+    fn status_round_trip() {
+        for status in [Status::Passive, Status::Active, Status::NeedsAttention] {
+            assert_eq!(status, Status::from(status.as_str()));
+            assert_eq!(status.as_str(), status.to_string());
+        }
+    }
+
+    #[test]
+    fn category_round_trip() {
+        for category in [
+            Category::ApplicationStatus,
+            Category::Communications,
+            Category::SystemServices,
+            Category::Hardware,
+        ] {
+            assert_eq!(category, Category::from(category.as_str()));
+            assert_eq!(category.as_str(), category.to_string());
+        }
+    }
+
+    fn tooltip(description: &str) -> Tooltip {
+        Tooltip {
+            icon_name: String::new(),
+            icon_data: vec![],
+            title: "Title".to_string(),
+            description: description.to_string(),
+        }
+    }
+
+    #[test]
+    fn tooltip_plain_text_nested() {
+        let tooltip = tooltip(
+            r#"<b>Playing: <i>Song</i></b><br/>by <a href="https://example.com">Artist</a>"#,
+        );
+
+        assert_eq!("Playing: Song\nby Artist", tooltip.plain_text());
+        assert!(tooltip.raw().starts_with("<b>"));
+    }
+
+    #[test]
+    fn tooltip_plain_text_entities() {
+        let tooltip = tooltip("Tom &amp; Jerry &lt;3 &#169; &#x2764; & &bogus; a < b");
+        assert_eq!("Tom & Jerry <3 © ❤ & &bogus; a < b", tooltip.plain_text());
+    }
+
+    #[test]
+    fn pixmap_best_fit() {
+        let pixmaps = [48, 16, 24]
+            .map(|size| IconPixmap {
+                width: size,
+                height: size,
+                pixels: vec![],
+            })
+            .to_vec();
+
+        let width = |size| IconPixmap::best_fit(&pixmaps, size).map(|pixmap| pixmap.width);
+
+        assert_eq!(Some(24), width(22));
+        assert_eq!(Some(24), width(24));
+        assert_eq!(Some(16), width(8));
+        assert_eq!(Some(48), width(64));
+        assert_eq!(
+            None,
+            IconPixmap::best_fit(&[], 22).map(|pixmap| pixmap.width)
+        );
+    }
+
+    #[test]
+    fn interaction_mode() {
+        let mut item = StatusNotifierItem {
+            menu: Some("/MenuBar".to_string()),
+            item_is_menu: true,
+            ..Default::default()
+        };
+        assert_eq!(InteractionMode::Menu, item.interaction_mode());
+
+        item.item_is_menu = false;
+        assert_eq!(InteractionMode::Both, item.interaction_mode());
+
+        item.menu = None;
+        assert_eq!(InteractionMode::Activate, item.interaction_mode());
+
+        item.item_is_menu = true;
+        assert_eq!(InteractionMode::Activate, item.interaction_mode());
+    }
+}
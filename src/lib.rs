@@ -31,6 +31,10 @@ pub mod data;
 /// and associated types.
 pub mod client;
 
+/// Blocking wrapper around the client,
+/// for use without a Tokio runtime.
+pub mod blocking;
+
 /// Error and result types.
 pub mod error;
 
@@ -43,6 +47,21 @@ pub mod menu;
 #[cfg(feature = "dbusmenu-gtk3")]
 pub mod gtk_menu;
 
+/// Resolving item icons to files or pixmaps.
+#[cfg(feature = "icon")]
+pub mod icon;
+
+/// Mock client for unit testing consumers without a bus.
+#[cfg(feature = "test")]
+pub mod mock;
+
+#[cfg(feature = "serde")]
+mod serde_base64;
+
+mod queue;
+
+mod sync;
+
 pub(crate) mod names {
     pub const WATCHER_BUS: &str = "org.kde.StatusNotifierWatcher";
     pub const WATCHER_OBJECT: &str = "/StatusNotifierWatcher";
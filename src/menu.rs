@@ -5,19 +5,38 @@ use std::collections::HashMap;
 use zbus::zvariant::{Array, OwnedValue, Structure, Value};
 
 /// A menu that should be displayed when clicking corresponding tray icon
-#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
 pub struct TrayMenu {
-    /// The unique identifier of the menu
-    pub id: u32,
+    /// The layout revision of the menu.
+    ///
+    /// This increases each time the application changes the menu layout,
+    /// so can be used to detect out-of-order updates.
+    pub revision: u32,
     /// A recursive list of submenus
     pub submenus: Vec<MenuItem>,
+    /// The direction the menu's text is laid out in.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub text_direction: TextDirection,
+    /// Whether the menu needs the user's attention.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub status: MenuStatus,
+    /// Extra paths to look up the menu items' [`MenuItem::icon_name`] in,
+    /// for applications which ship their own icons.
+    ///
+    /// This is separate from the item's own theme path.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub icon_theme_path: Vec<String>,
 }
 
 /// List of properties taken from:
 /// <https://github.com/AyatanaIndicators/libdbusmenu/blob/4d03141aea4e2ad0f04ab73cf1d4f4bcc4a19f6c/libdbusmenu-glib/dbus-menu.xml#L75>
-#[derive(Debug, Clone, Deserialize, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Clone, Deserialize)]
 pub struct MenuItem {
-    /// Unique numeric id
+    /// Unique numeric id.
+    ///
+    /// This is the id to use as `submenu_id` when activating the item.
     pub id: i32,
 
     /// Either a standard menu item or a separator [`MenuType`]
@@ -31,12 +50,15 @@ pub struct MenuItem {
     ///    character is the access key.
     pub label: Option<String>,
     /// Whether the item can be activated or not.
+    /// Defaults to `true`.
     pub enabled: bool,
     /// True if the item is visible in the menu.
+    /// Defaults to `true`.
     pub visible: bool,
     /// Icon name of the item, following the freedesktop.org icon spec.
     pub icon_name: Option<String>,
     /// PNG data of the icon.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64"))]
     pub icon_data: Option<Vec<u8>>,
     /// The shortcut of the item. Each array represents the key press
     /// in the list of keypresses. Each list of strings contains a list of
@@ -48,12 +70,15 @@ pub struct MenuItem {
     /// - A complex shortcut like Ctrl+Q, Alt+X is represented as:
     ///   [["Control", "Q"], ["Alt", "X"]]
     pub shortcut: Option<Vec<Vec<String>>>,
+    /// Text describing the item for screen readers.
+    pub accessible_desc: Option<String>,
     /// How the menuitem feels the information it's displaying to the
     /// user should be presented.
     /// See [`ToggleType`].
     pub toggle_type: ToggleType,
     /// Describe the current state of a "togglable" item.
     /// See [`ToggleState`].
+    /// Items which do not set the property are [`ToggleState::Indeterminate`].
     ///
     /// # Note:
     /// The implementation does not itself handle ensuring that only one
@@ -63,6 +88,7 @@ pub struct MenuItem {
     pub toggle_state: ToggleState,
     /// If the menu item has children this property should be set to
     /// "submenu".
+    /// See [`MenuItem::has_submenu`].
     pub children_display: Option<String>,
     /// How the menuitem feels the information it's displaying to the
     /// user should be presented.
@@ -72,6 +98,7 @@ pub struct MenuItem {
     pub submenu: Vec<MenuItem>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct MenuDiff {
     pub id: i32,
@@ -79,8 +106,11 @@ pub struct MenuDiff {
     pub remove: Vec<String>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[derive(Debug, Clone, Deserialize, Default)]
 pub struct MenuItemUpdate {
+    /// Either a standard menu item or a separator [`MenuType`]
+    pub menu_type: Option<MenuType>,
     /// Text of the item, except that:
     ///  - two consecutive underscore characters "__" are displayed as a
     ///    single underscore,
@@ -96,7 +126,16 @@ pub struct MenuItemUpdate {
     /// Icon name of the item, following the freedesktop.org icon spec.
     pub icon_name: Option<Option<String>>,
     /// PNG data of the icon.
+    #[cfg_attr(feature = "serde", serde(with = "crate::serde_base64"))]
     pub icon_data: Option<Option<Vec<u8>>>,
+    /// The shortcut of the item.
+    /// See [`MenuItem::shortcut`].
+    pub shortcut: Option<Option<Vec<Vec<String>>>>,
+    /// Text describing the item for screen readers.
+    pub accessible_desc: Option<Option<String>>,
+    /// If the item can be toggled, this property should be set to:
+    /// See [`ToggleType`].
+    pub toggle_type: Option<ToggleType>,
     /// Describe the current state of a "togglable" item.
     /// See [`ToggleState`].
     ///
@@ -106,12 +145,16 @@ pub struct MenuItemUpdate {
     /// "on" and "indeterminate" items simultaneously; maintaining this
     /// policy is up to the toolkit wrappers.
     pub toggle_state: Option<ToggleState>,
+    /// Whether the item opens a submenu.
+    /// See [`MenuItem::children_display`].
+    pub children_display: Option<Option<String>>,
     /// How the menuitem feels the information it's displaying to the
     /// user should be presented.
     /// See [`Disposition`]
     pub disposition: Option<Disposition>,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq, Default)]
 pub enum MenuType {
     ///  a separator
@@ -130,6 +173,7 @@ impl From<&str> for MenuType {
     }
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq, Default)]
 pub enum ToggleType {
     /// Item is an independent togglable item
@@ -153,14 +197,15 @@ impl From<&str> for ToggleType {
 }
 
 /// Describe the current state of a "togglable" item.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq, Default)]
 pub enum ToggleState {
     /// This item is toggled
-    #[default]
     On,
     /// Item is not toggled
     Off,
     /// Item is not toggalble
+    #[default]
     Indeterminate,
 }
 
@@ -174,6 +219,10 @@ impl From<i32> for ToggleState {
     }
 }
 
+/// How the menu item's information should be presented.
+///
+/// Unknown or missing values fall back to [`Disposition::Normal`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
 #[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq, Default)]
 pub enum Disposition {
     /// a standard menu item
@@ -198,6 +247,363 @@ impl From<&str> for Disposition {
     }
 }
 
+/// The direction text is laid out in, from the menu's `TextDirection` property.
+///
+/// Unknown or missing values fall back to [`TextDirection::LeftToRight`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq, Default)]
+pub enum TextDirection {
+    /// Left to right, such as for English.
+    #[default]
+    LeftToRight,
+    /// Right to left, such as for Arabic or Hebrew.
+    RightToLeft,
+}
+
+impl From<&str> for TextDirection {
+    fn from(value: &str) -> Self {
+        match value {
+            "rtl" => Self::RightToLeft,
+            _ => Self::default(),
+        }
+    }
+}
+
+/// The state of the menu, from its `Status` property.
+///
+/// Unknown or missing values fall back to [`MenuStatus::Normal`].
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[derive(Debug, Deserialize, Copy, Clone, Eq, PartialEq, Default)]
+pub enum MenuStatus {
+    /// The menu is displayed as usual.
+    #[default]
+    Normal,
+    /// The menu has something which needs the user's attention,
+    /// so should be displayed more prominently.
+    Notice,
+}
+
+impl From<&str> for MenuStatus {
+    fn from(value: &str) -> Self {
+        match value {
+            "notice" => Self::Notice,
+            _ => Self::default(),
+        }
+    }
+}
+
+/// An event sent to the application when the user interacts with a menu item.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Default)]
+pub enum MenuEventType {
+    /// The item was activated.
+    #[default]
+    Clicked,
+    /// The pointer is over the item.
+    Hovered,
+    /// The item's submenu was shown.
+    Opened,
+    /// The item's submenu was hidden.
+    Closed,
+}
+
+impl MenuEventType {
+    /// Gets the event id sent over the bus.
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Clicked => "clicked",
+            Self::Hovered => "hovered",
+            Self::Opened => "opened",
+            Self::Closed => "closed",
+        }
+    }
+}
+
+/// The properties requested when fetching a menu layout.
+///
+/// This is every property the parser reads,
+/// so that unused properties are not sent over the bus.
+pub(crate) const MENU_PROPERTIES: &[&str] = &[
+    "type",
+    "label",
+    "enabled",
+    "visible",
+    "icon-name",
+    "icon-data",
+    "shortcut",
+    "accessible-desc",
+    "toggle-type",
+    "toggle-state",
+    "children-display",
+    "disposition",
+];
+
+/// Tracks the layout revisions of a menu,
+/// so that stale layouts can be ignored.
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct MenuRevision {
+    /// The revision of the last applied layout.
+    applied: Option<u32>,
+    /// The revision of the last `LayoutUpdated` signal.
+    signalled: Option<u32>,
+}
+
+impl MenuRevision {
+    /// Records a `LayoutUpdated` signal for `revision`.
+    ///
+    /// Revisions only go backwards if the menu has been reset,
+    /// for example because the application restarted,
+    /// so after any backwards jump the next layout is accepted whatever its revision.
+    pub(crate) fn signal(&mut self, revision: u32) {
+        if self
+            .applied
+            .max(self.signalled)
+            .is_some_and(|newest| revision < newest)
+        {
+            self.applied = None;
+        }
+
+        self.signalled = Some(revision);
+    }
+
+    /// Checks whether a fetched layout with `revision` should be applied,
+    /// recording it as the latest revision if so.
+    ///
+    /// Layouts older than the last applied one are rejected,
+    /// as they were fetched before it.
+    pub(crate) fn accept(&mut self, revision: u32) -> bool {
+        if self.applied.is_some_and(|applied| revision < applied) {
+            return false;
+        }
+
+        self.applied = Some(revision);
+        true
+    }
+}
+
+/// Gets the `DBusMenu` name of a shortcut modifier in lowercase,
+/// resolving common aliases.
+fn normalize_modifier(modifier: &str) -> String {
+    let modifier = modifier.to_lowercase();
+
+    match modifier.as_str() {
+        "ctrl" | "primary" => "control".to_string(),
+        "meta" | "logo" | "win" | "mod4" => "super".to_string(),
+        "mod1" => "alt".to_string(),
+        _ => modifier,
+    }
+}
+
+impl TrayMenu {
+    /// Finds the item with the matching id,
+    /// searching the whole menu tree.
+    ///
+    /// The id is the one to pass when activating the item,
+    /// see [`crate::client::Client::activate_menu_item`].
+    #[must_use]
+    pub fn find(&self, id: i32) -> Option<&MenuItem> {
+        MenuItem::find(&self.submenus, id)
+    }
+
+    /// Finds the enabled, visible item whose shortcut is `key`
+    /// pressed with exactly `modifiers`, searching the whole menu tree.
+    ///
+    /// Modifiers can be given in any order, and common aliases are accepted,
+    /// so `ctrl` matches `Control` and `meta` or `logo` match `Super`.
+    /// Modifiers and keys are compared case-insensitively,
+    /// and only shortcuts of a single key press can match.
+    #[must_use]
+    pub fn find_by_shortcut(&self, modifiers: &[String], key: &str) -> Option<&MenuItem> {
+        let mut modifiers = modifiers
+            .iter()
+            .map(|modifier| normalize_modifier(modifier))
+            .collect::<Vec<_>>();
+        modifiers.sort_unstable();
+        modifiers.dedup();
+
+        let key = key.to_lowercase();
+
+        let matches = |press: &Vec<String>| {
+            press.split_last().is_some_and(|(last, rest)| {
+                let mut rest = rest
+                    .iter()
+                    .map(|modifier| normalize_modifier(modifier))
+                    .collect::<Vec<_>>();
+                rest.sort_unstable();
+                rest.dedup();
+
+                last.to_lowercase() == key && rest == modifiers
+            })
+        };
+
+        self.flatten()
+            .into_iter()
+            .map(|(_, item)| item)
+            .find(|item| {
+                item.enabled
+                    && item.visible
+                    && matches!(item.shortcut.as_deref(), Some([press]) if matches(press))
+            })
+    }
+
+    /// Gets every item in the menu tree with its id,
+    /// in depth-first order, so each item is followed by its submenu.
+    ///
+    /// This is the order the items appear in when the menu is fully expanded.
+    #[must_use]
+    pub fn flatten(&self) -> Vec<(i32, &MenuItem)> {
+        fn visit<'a>(items: &'a [MenuItem], out: &mut Vec<(i32, &'a MenuItem)>) {
+            for item in items {
+                out.push((item.id, item));
+                visit(&item.submenu, out);
+            }
+        }
+
+        let mut items = vec![];
+        visit(&self.submenus, &mut items);
+        items
+    }
+
+    /// Applies each diff to the item with the matching id,
+    /// searching the whole menu tree.
+    ///
+    /// Removed properties are reset to their defaults.
+    /// Diffs for ids which are not in the menu are ignored.
+    pub fn apply_diff(&mut self, diffs: &[MenuDiff]) {
+        for diff in diffs {
+            if let Some(item) = MenuItem::find_mut(&mut self.submenus, diff.id) {
+                item.apply_diff(diff);
+            }
+        }
+    }
+
+    /// Replaces the children of the item with id `parent_id`
+    /// with the submenus of `subtree`, which must be the layout fetched for that item.
+    /// The menu takes on the revision of `subtree`.
+    ///
+    /// Returns `false` if the parent is not in the menu,
+    /// in which case the menu is unchanged.
+    pub fn apply_subtree(&mut self, parent_id: i32, subtree: TrayMenu) -> bool {
+        let submenus = if parent_id == 0 {
+            &mut self.submenus
+        } else if let Some(parent) = MenuItem::find_mut(&mut self.submenus, parent_id) {
+            &mut parent.submenu
+        } else {
+            return false;
+        };
+
+        *submenus = subtree.submenus;
+        self.revision = subtree.revision;
+
+        true
+    }
+}
+
+impl MenuItem {
+    /// Checks whether the item is a separator,
+    /// which should be drawn as a divider and cannot be activated.
+    #[must_use]
+    pub fn is_separator(&self) -> bool {
+        self.menu_type == MenuType::Separator
+    }
+
+    /// Checks whether the item opens a submenu.
+    ///
+    /// This is true even if the submenu's children have not been loaded yet,
+    /// in which case they should be fetched when it is opened,
+    /// for example using [`Client::get_submenu`](crate::client::Client::get_submenu).
+    #[must_use]
+    pub fn has_submenu(&self) -> bool {
+        self.children_display.as_deref() == Some("submenu")
+    }
+
+    fn find(items: &[MenuItem], id: i32) -> Option<&MenuItem> {
+        items.iter().find_map(|item| {
+            if item.id == id {
+                Some(item)
+            } else {
+                Self::find(&item.submenu, id)
+            }
+        })
+    }
+
+    fn find_mut(items: &mut [MenuItem], id: i32) -> Option<&mut MenuItem> {
+        for item in items {
+            if item.id == id {
+                return Some(item);
+            }
+
+            if let Some(item) = Self::find_mut(&mut item.submenu, id) {
+                return Some(item);
+            }
+        }
+
+        None
+    }
+
+    /// Applies a diff to this item,
+    /// resetting removed properties before setting updated ones.
+    fn apply_diff(&mut self, diff: &MenuDiff) {
+        for property in &diff.remove {
+            match property.as_str() {
+                "type" => self.menu_type = MenuType::default(),
+                "label" => self.label = None,
+                "enabled" => self.enabled = true,
+                "visible" => self.visible = true,
+                "icon-name" => self.icon_name = None,
+                "icon-data" => self.icon_data = None,
+                "shortcut" => self.shortcut = None,
+                "accessible-desc" => self.accessible_desc = None,
+                "toggle-type" => self.toggle_type = ToggleType::default(),
+                "toggle-state" => self.toggle_state = ToggleState::Indeterminate,
+                "children-display" => self.children_display = None,
+                "disposition" => self.disposition = Disposition::default(),
+                _ => {}
+            }
+        }
+
+        let update = &diff.update;
+
+        if let Some(menu_type) = update.menu_type {
+            self.menu_type = menu_type;
+        }
+        if let Some(label) = &update.label {
+            self.label.clone_from(label);
+        }
+        if let Some(enabled) = update.enabled {
+            self.enabled = enabled;
+        }
+        if let Some(visible) = update.visible {
+            self.visible = visible;
+        }
+        if let Some(icon_name) = &update.icon_name {
+            self.icon_name.clone_from(icon_name);
+        }
+        if let Some(icon_data) = &update.icon_data {
+            self.icon_data.clone_from(icon_data);
+        }
+        if let Some(shortcut) = &update.shortcut {
+            self.shortcut.clone_from(shortcut);
+        }
+        if let Some(accessible_desc) = &update.accessible_desc {
+            self.accessible_desc.clone_from(accessible_desc);
+        }
+        if let Some(toggle_type) = update.toggle_type {
+            self.toggle_type = toggle_type;
+        }
+        if let Some(toggle_state) = update.toggle_state {
+            self.toggle_state = toggle_state;
+        }
+        if let Some(children_display) = &update.children_display {
+            self.children_display.clone_from(children_display);
+        }
+        if let Some(disposition) = update.disposition {
+            self.disposition = disposition;
+        }
+    }
+}
+
 impl TryFrom<MenuLayout> for TrayMenu {
     type Error = Error;
 
@@ -209,9 +615,14 @@ impl TryFrom<MenuLayout> for TrayMenu {
             .map(MenuItem::try_from)
             .collect::<std::result::Result<_, _>>()?;
 
+        // the layout does not include the menu's own properties,
+        // so these are filled in by the caller
         Ok(Self {
-            id: value.id,
+            revision: value.id,
             submenus,
+            text_direction: TextDirection::default(),
+            status: MenuStatus::default(),
+            icon_theme_path: Vec::new(),
         })
     }
 }
@@ -224,13 +635,7 @@ impl TryFrom<&OwnedValue> for MenuItem {
 
         let mut fields = structure.fields().iter();
 
-        // defaults for enabled/visible are true
-        // and setting here avoids having to provide a full `Default` impl
-        let mut menu = MenuItem {
-            enabled: true,
-            visible: true,
-            ..Default::default()
-        };
+        let mut menu = MenuItem::default();
 
         if let Some(Value::I32(id)) = fields.next() {
             menu.id = *id;
@@ -260,6 +665,14 @@ impl TryFrom<&OwnedValue> for MenuItem {
                 menu.icon_data = Some(get_icon_data(array)?);
             }
 
+            if let Some(array) = dict.get::<&str, &Array>(&"shortcut")? {
+                menu.shortcut = Some(get_shortcut(array)?);
+            }
+
+            menu.accessible_desc = dict
+                .get::<&str, &str>(&"accessible-desc")?
+                .map(str::to_string);
+
             if let Some(disposition) = dict
                 .get::<&str, &str>(&"disposition")
                 .ok()
@@ -269,12 +682,12 @@ impl TryFrom<&OwnedValue> for MenuItem {
                 menu.disposition = disposition;
             }
 
+            // the spec defaults to -1 (indeterminate) when unset
             menu.toggle_state = dict
                 .get::<&str, i32>(&"toggle-state")
                 .ok()
                 .flatten()
-                .map(ToggleState::from)
-                .unwrap_or_default();
+                .map_or(ToggleState::Indeterminate, ToggleState::from);
 
             menu.toggle_type = dict
                 .get::<&str, &str>(&"toggle-type")
@@ -336,6 +749,50 @@ impl TryFrom<PropertiesUpdate<'_>> for Vec<MenuDiff> {
     }
 }
 
+impl Default for MenuItem {
+    fn default() -> Self {
+        // enabled and visible are true unless the item says otherwise
+        Self {
+            id: 0,
+            menu_type: MenuType::default(),
+            label: None,
+            enabled: true,
+            visible: true,
+            icon_name: None,
+            icon_data: None,
+            shortcut: None,
+            accessible_desc: None,
+            toggle_type: ToggleType::default(),
+            toggle_state: ToggleState::default(),
+            children_display: None,
+            disposition: Disposition::default(),
+            submenu: vec![],
+        }
+    }
+}
+
+impl MenuDiff {
+    /// Creates a diff setting each of `properties` on the item with `id`.
+    pub(crate) fn from_properties(
+        id: i32,
+        properties: &HashMap<String, OwnedValue>,
+    ) -> Result<Self> {
+        let updated = UpdatedProps {
+            id,
+            fields: properties
+                .iter()
+                .map(|(name, value)| (name.as_str(), Value::from(value.clone())))
+                .collect(),
+        };
+
+        Ok(Self {
+            id,
+            update: updated.try_into()?,
+            ..Default::default()
+        })
+    }
+}
+
 impl TryFrom<UpdatedProps<'_>> for MenuItemUpdate {
     type Error = Error;
 
@@ -352,7 +809,22 @@ impl TryFrom<UpdatedProps<'_>> for MenuItemUpdate {
             None
         };
 
+        let shortcut = if let Some(arr) = dict
+            .get("shortcut")
+            .map(Value::downcast_ref::<&Array>)
+            .transpose()?
+        {
+            Some(Some(get_shortcut(arr)?))
+        } else {
+            None
+        };
+
         Ok(Self {
+            menu_type: dict
+                .get("type")
+                .and_then(|v| Value::downcast_ref::<&str>(v).ok())
+                .map(MenuType::from),
+
             label: dict
                 .get("label")
                 .map(|v| v.downcast_ref::<&str>().map(ToString::to_string).ok()),
@@ -371,11 +843,26 @@ impl TryFrom<UpdatedProps<'_>> for MenuItemUpdate {
 
             icon_data,
 
+            shortcut,
+
+            accessible_desc: dict
+                .get("accessible-desc")
+                .map(|v| v.downcast_ref::<&str>().map(ToString::to_string).ok()),
+
+            toggle_type: dict
+                .get("toggle-type")
+                .and_then(|v| Value::downcast_ref::<&str>(v).ok())
+                .map(ToggleType::from),
+
             toggle_state: dict
                 .get("toggle-state")
                 .and_then(|v| Value::downcast_ref::<i32>(v).ok())
                 .map(ToggleState::from),
 
+            children_display: dict
+                .get("children-display")
+                .map(|v| v.downcast_ref::<&str>().map(ToString::to_string).ok()),
+
             disposition: dict
                 .get("disposition")
                 .and_then(|v| Value::downcast_ref::<&str>(v).ok())
@@ -390,3 +877,545 @@ fn get_icon_data(array: &Array) -> Result<Vec<u8>> {
         .map(|v| v.downcast_ref::<u8>().map_err(Into::into))
         .collect::<Result<Vec<_>>>()
 }
+
+/// Parses a shortcut, which is a list of key presses,
+/// each made up of any modifiers followed by the key.
+fn get_shortcut(array: &Array) -> Result<Vec<Vec<String>>> {
+    array
+        .iter()
+        .map(|keys| {
+            keys.downcast_ref::<&Array>()?
+                .iter()
+                .map(|key| {
+                    key.downcast_ref::<&str>()
+                        .map(ToString::to_string)
+                        .map_err(Into::into)
+                })
+                .collect()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use zbus::zvariant::{Str, StructureBuilder};
+
+    /// Builds a `(ia{sv}av)` layout node, as returned by `GetLayout`.
+    fn layout_item(
+        id: i32,
+        props: Vec<(&str, Value<'static>)>,
+        children: Vec<Value<'static>>,
+    ) -> OwnedValue {
+        let props: HashMap<&str, Value> = props.into_iter().collect();
+        let structure = StructureBuilder::new()
+            .add_field(id)
+            .add_field(props)
+            .add_field(children)
+            .build()
+            .expect("valid structure");
+
+        OwnedValue::try_from(Value::from(structure)).expect("owned value")
+    }
+
+    #[test]
+    fn parse_deeply_nested() {
+        const DEPTH: i32 = 12;
+
+        let mut node = layout_item(DEPTH, vec![("label", Value::from("Leaf"))], vec![]);
+        for id in (1..DEPTH).rev() {
+            node = layout_item(id, vec![], vec![Value::from(node)]);
+        }
+
+        let mut item = MenuItem::try_from(&node).expect("valid item");
+        let mut depth = 1;
+        while let [child] = item.submenu.as_slice() {
+            item = child.clone();
+            depth += 1;
+        }
+
+        assert_eq!(DEPTH, depth);
+        assert_eq!(DEPTH, item.id);
+        assert_eq!(Some("Leaf"), item.label.as_deref());
+    }
+
+    #[test]
+    fn parse_checkbox_item() {
+        let value = layout_item(
+            3,
+            vec![
+                ("label", Value::from("Show _Notifications")),
+                ("toggle-type", Value::from("checkmark")),
+                ("toggle-state", Value::from(1)),
+            ],
+            vec![],
+        );
+        let item = MenuItem::try_from(&value).expect("valid item");
+
+        assert_eq!(3, item.id);
+        assert_eq!(ToggleType::Checkmark, item.toggle_type);
+        assert_eq!(ToggleState::On, item.toggle_state);
+    }
+
+    #[test]
+    fn parse_separator() {
+        let separator = layout_item(2, vec![("type", Value::from("separator"))], vec![]);
+        let separator = MenuItem::try_from(&separator).expect("valid item");
+        assert_eq!(MenuType::Separator, separator.menu_type);
+        assert!(separator.is_separator());
+        assert!(separator.label.is_none());
+
+        let standard = layout_item(3, vec![("type", Value::from("standard"))], vec![]);
+        let standard = MenuItem::try_from(&standard).expect("valid item");
+        assert_eq!(MenuType::Standard, standard.menu_type);
+
+        let unset = layout_item(4, vec![("label", Value::from("Quit"))], vec![]);
+        let unset = MenuItem::try_from(&unset).expect("valid item");
+        assert!(!unset.is_separator());
+    }
+
+    #[test]
+    fn parse_unloaded_submenu() {
+        let unloaded = layout_item(
+            5,
+            vec![
+                ("label", Value::from("Recent")),
+                ("children-display", Value::from("submenu")),
+            ],
+            vec![],
+        );
+        let unloaded = MenuItem::try_from(&unloaded).expect("valid item");
+        assert!(unloaded.has_submenu());
+        assert!(unloaded.submenu.is_empty());
+
+        let leaf = layout_item(6, vec![("label", Value::from("Quit"))], vec![]);
+        let mut leaf = MenuItem::try_from(&leaf).expect("valid item");
+        assert!(!leaf.has_submenu());
+
+        leaf.apply_diff(&MenuDiff {
+            id: 6,
+            update: MenuItemUpdate {
+                children_display: Some(Some("submenu".to_string())),
+                ..Default::default()
+            },
+            ..Default::default()
+        });
+        assert!(leaf.has_submenu());
+    }
+
+    #[test]
+    fn parse_text_direction_and_status() {
+        assert_eq!(TextDirection::RightToLeft, TextDirection::from("rtl"));
+        assert_eq!(TextDirection::LeftToRight, TextDirection::from("ltr"));
+        assert_eq!(TextDirection::LeftToRight, TextDirection::from(""));
+
+        assert_eq!(MenuStatus::Notice, MenuStatus::from("notice"));
+        assert_eq!(MenuStatus::Normal, MenuStatus::from("normal"));
+    }
+
+    #[test]
+    fn parse_shortcut_and_accessible_desc() {
+        let value = layout_item(
+            4,
+            vec![
+                ("label", Value::from("_Quit")),
+                ("shortcut", Value::from(vec![vec!["Control", "q"]])),
+                ("accessible-desc", Value::from("Quit the application")),
+            ],
+            vec![],
+        );
+        let item = MenuItem::try_from(&value).expect("valid item");
+
+        assert_eq!(
+            Some(vec![vec!["Control".to_string(), "q".to_string()]]),
+            item.shortcut
+        );
+        assert_eq!(
+            Some("Quit the application"),
+            item.accessible_desc.as_deref()
+        );
+
+        let plain = layout_item(5, vec![("label", Value::from("Open"))], vec![]);
+        let plain = MenuItem::try_from(&plain).expect("valid item");
+        assert!(plain.shortcut.is_none());
+        assert!(plain.accessible_desc.is_none());
+    }
+
+    #[test]
+    fn parse_toggle_state() {
+        let radio = layout_item(
+            1,
+            vec![
+                ("toggle-type", Value::from("radio")),
+                ("toggle-state", Value::from(0)),
+            ],
+            vec![],
+        );
+        let radio = MenuItem::try_from(&radio).expect("valid item");
+        assert_eq!(ToggleType::Radio, radio.toggle_type);
+        assert_eq!(ToggleState::Off, radio.toggle_state);
+
+        let indeterminate = layout_item(
+            2,
+            vec![
+                ("toggle-type", Value::from("checkmark")),
+                ("toggle-state", Value::from(-1)),
+            ],
+            vec![],
+        );
+        let indeterminate = MenuItem::try_from(&indeterminate).expect("valid item");
+        assert_eq!(ToggleState::Indeterminate, indeterminate.toggle_state);
+
+        let plain = layout_item(3, vec![("label", Value::from("Quit"))], vec![]);
+        let plain = MenuItem::try_from(&plain).expect("valid item");
+        assert_eq!(ToggleType::CannotBeToggled, plain.toggle_type);
+        assert_eq!(ToggleState::Indeterminate, plain.toggle_state);
+        assert_eq!(plain.toggle_state, MenuItem::default().toggle_state);
+    }
+
+    #[test]
+    fn parse_disposition() {
+        let alert = layout_item(1, vec![("disposition", Value::from("alert"))], vec![]);
+        let alert = MenuItem::try_from(&alert).expect("valid item");
+        assert_eq!(Disposition::Alert, alert.disposition);
+
+        let unknown = layout_item(2, vec![("disposition", Value::from("shouty"))], vec![]);
+        let unknown = MenuItem::try_from(&unknown).expect("valid item");
+        assert_eq!(Disposition::Normal, unknown.disposition);
+
+        let unset = layout_item(3, vec![], vec![]);
+        let unset = MenuItem::try_from(&unset).expect("valid item");
+        assert_eq!(Disposition::Normal, unset.disposition);
+    }
+
+    #[test]
+    fn revision_increasing() {
+        let mut revision = MenuRevision::default();
+        assert!(revision.accept(1));
+        assert!(revision.accept(2));
+        assert!(revision.accept(2));
+        assert!(revision.accept(10));
+    }
+
+    #[test]
+    fn revision_stale() {
+        let mut revision = MenuRevision::default();
+        assert!(revision.accept(100));
+        assert!(!revision.accept(95));
+        assert!(revision.accept(101));
+
+        // a signal for a newer revision does not make older layouts current
+        revision.signal(102);
+        assert!(!revision.accept(100));
+        assert!(revision.accept(102));
+    }
+
+    #[test]
+    fn revision_reset() {
+        let mut revision = MenuRevision::default();
+        assert!(revision.accept(100));
+        assert!(!revision.accept(1));
+
+        revision.signal(1);
+        assert!(revision.accept(1));
+        assert!(revision.accept(2));
+        assert!(!revision.accept(1));
+    }
+
+    #[test]
+    fn revision_reset_from_low_revision() {
+        let mut revision = MenuRevision::default();
+        assert!(revision.accept(10));
+
+        revision.signal(1);
+        assert!(revision.accept(1));
+
+        // a reset signalled in the same burst as a newer revision
+        revision.signal(11);
+        revision.signal(2);
+        assert!(revision.accept(2));
+    }
+
+    fn diff_menu() -> TrayMenu {
+        TrayMenu {
+            revision: 1,
+            submenus: vec![MenuItem {
+                id: 1,
+                label: Some("Mute".to_string()),
+                enabled: false,
+                visible: true,
+                toggle_type: ToggleType::Checkmark,
+                toggle_state: ToggleState::Off,
+                disposition: Disposition::Warning,
+                ..Default::default()
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn apply_diff_set() {
+        let mut menu = diff_menu();
+        menu.apply_diff(&[MenuDiff {
+            id: 1,
+            update: MenuItemUpdate {
+                label: Some(Some("Unmute".to_string())),
+                enabled: Some(true),
+                toggle_state: Some(ToggleState::On),
+                ..Default::default()
+            },
+            ..Default::default()
+        }]);
+
+        let item = &menu.submenus[0];
+        assert_eq!(Some("Unmute"), item.label.as_deref());
+        assert!(item.enabled);
+        assert_eq!(ToggleState::On, item.toggle_state);
+        assert_eq!(Disposition::Warning, item.disposition);
+    }
+
+    #[test]
+    fn apply_diff_disable() {
+        let mut menu = diff_menu();
+        menu.submenus[0].enabled = true;
+
+        let properties = HashMap::from([("enabled".to_string(), OwnedValue::from(false))]);
+        let diff = MenuDiff::from_properties(1, &properties).expect("valid properties");
+        assert_eq!(Some(false), diff.update.enabled);
+        assert_eq!(None, diff.update.visible);
+
+        menu.apply_diff(&[diff]);
+
+        let item = &menu.submenus[0];
+        assert!(!item.enabled);
+        assert!(item.visible);
+    }
+
+    #[test]
+    fn apply_diff_types() {
+        let mut menu = diff_menu();
+
+        let properties = HashMap::from([
+            (
+                "type".to_string(),
+                OwnedValue::from(Str::from_static("separator")),
+            ),
+            (
+                "toggle-type".to_string(),
+                OwnedValue::from(Str::from_static("radio")),
+            ),
+        ]);
+        let diff = MenuDiff::from_properties(1, &properties).expect("valid properties");
+        assert_eq!(Some(MenuType::Separator), diff.update.menu_type);
+        assert_eq!(Some(ToggleType::Radio), diff.update.toggle_type);
+
+        menu.apply_diff(&[diff]);
+
+        let item = &menu.submenus[0];
+        assert_eq!(MenuType::Separator, item.menu_type);
+        assert_eq!(ToggleType::Radio, item.toggle_type);
+    }
+
+    #[test]
+    fn apply_diff_remove() {
+        let mut menu = diff_menu();
+        menu.apply_diff(&[MenuDiff {
+            id: 1,
+            remove: ["label", "enabled", "toggle-state", "disposition"]
+                .map(ToString::to_string)
+                .to_vec(),
+            ..Default::default()
+        }]);
+
+        let item = &menu.submenus[0];
+        assert_eq!(None, item.label);
+        assert!(item.enabled);
+        assert_eq!(ToggleState::Indeterminate, item.toggle_state);
+        assert_eq!(Disposition::Normal, item.disposition);
+        assert_eq!(ToggleType::Checkmark, item.toggle_type);
+    }
+
+    #[test]
+    fn apply_diff_unknown_id() {
+        let mut menu = diff_menu();
+        menu.apply_diff(&[MenuDiff {
+            id: 42,
+            update: MenuItemUpdate {
+                label: Some(Some("Unmute".to_string())),
+                ..Default::default()
+            },
+            remove: vec!["label".to_string()],
+        }]);
+
+        assert_eq!(Some("Mute"), menu.submenus[0].label.as_deref());
+    }
+
+    #[test]
+    fn find_nested() {
+        let menu = TrayMenu {
+            revision: 1,
+            submenus: vec![MenuItem {
+                id: 1,
+                submenu: vec![MenuItem {
+                    id: 2,
+                    label: Some("Nested".to_string()),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            Some("Nested"),
+            menu.find(2).and_then(|item| item.label.as_deref())
+        );
+        assert!(menu.find(3).is_none());
+    }
+
+    #[test]
+    fn find_by_shortcut() {
+        let shortcut = |presses: &[&[&str]]| {
+            Some(
+                presses
+                    .iter()
+                    .map(|press| press.iter().map(ToString::to_string).collect())
+                    .collect(),
+            )
+        };
+
+        let menu = TrayMenu {
+            submenus: vec![
+                MenuItem {
+                    id: 1,
+                    shortcut: shortcut(&[&["Control", "Shift", "s"]]),
+                    enabled: false,
+                    ..Default::default()
+                },
+                MenuItem {
+                    id: 2,
+                    submenu: vec![MenuItem {
+                        id: 3,
+                        shortcut: shortcut(&[&["Shift", "Control", "s"]]),
+                        ..Default::default()
+                    }],
+                    ..Default::default()
+                },
+                MenuItem {
+                    id: 4,
+                    shortcut: shortcut(&[&["Control", "q"], &["Alt", "x"]]),
+                    ..Default::default()
+                },
+                MenuItem {
+                    id: 5,
+                    shortcut: shortcut(&[&["Control", "q"]]),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let modifiers = ["Control".to_string(), "Shift".to_string()];
+        assert_eq!(
+            Some(3),
+            menu.find_by_shortcut(&modifiers, "s").map(|item| item.id)
+        );
+        assert!(menu.find_by_shortcut(&modifiers[..1], "s").is_none());
+        assert!(menu.find_by_shortcut(&modifiers, "q").is_none());
+        // only the first press of a two-press shortcut does not match it
+        assert_eq!(
+            Some(5),
+            menu.find_by_shortcut(&modifiers[..1], "q")
+                .map(|item| item.id)
+        );
+
+        let modifiers = ["shift".to_string(), "ctrl".to_string()];
+        assert_eq!(
+            Some(3),
+            menu.find_by_shortcut(&modifiers, "S").map(|item| item.id)
+        );
+
+        // aliases of the same modifier count as one
+        let modifiers = ["CONTROL".to_string(), "Primary".to_string()];
+        assert_eq!(
+            Some(5),
+            menu.find_by_shortcut(&modifiers, "q").map(|item| item.id)
+        );
+        assert_eq!(
+            Some(5),
+            menu.find_by_shortcut(&modifiers[..1], "Q")
+                .map(|item| item.id)
+        );
+    }
+
+    #[test]
+    fn flatten_depth_first() {
+        let item = |id: i32, submenu: Vec<MenuItem>| MenuItem {
+            id,
+            submenu,
+            ..Default::default()
+        };
+
+        let menu = TrayMenu {
+            revision: 1,
+            submenus: vec![
+                item(1, vec![item(2, vec![item(3, vec![])]), item(4, vec![])]),
+                item(5, vec![]),
+            ],
+            ..Default::default()
+        };
+
+        let ids = menu
+            .flatten()
+            .into_iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        assert_eq!(vec![1, 2, 3, 4, 5], ids);
+    }
+
+    #[test]
+    fn apply_subtree_nested() {
+        let mut menu = TrayMenu {
+            revision: 1,
+            submenus: vec![MenuItem {
+                id: 1,
+                submenu: vec![MenuItem {
+                    id: 2,
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+
+        let subtree = TrayMenu {
+            revision: 2,
+            submenus: vec![
+                MenuItem {
+                    id: 3,
+                    ..Default::default()
+                },
+                MenuItem {
+                    id: 4,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        assert!(!menu.apply_subtree(5, subtree.clone()));
+        assert_eq!(1, menu.revision);
+
+        assert!(menu.apply_subtree(1, subtree));
+        assert_eq!(2, menu.revision);
+        assert!(menu.find(2).is_none());
+        assert_eq!(
+            vec![3, 4],
+            menu.submenus[0]
+                .submenu
+                .iter()
+                .map(|item| item.id)
+                .collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use crate::client::{ActivateOutcome, ActivateRequest, Event, DEFAULT_CAPACITY};
+use crate::data::TrayItemMap;
+use crate::error::{Error, Result};
+use crate::sync::MutexExt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::broadcast;
+
+#[cfg(feature = "data")]
+use {crate::item::StatusNotifierItem, crate::menu::TrayMenu};
+
+/// Stand-in for [`Client`](crate::client::Client) which never touches the bus,
+/// for unit testing code which consumes tray events.
+///
+/// Events are driven manually using [`MockClient::send`],
+/// and are delivered to subscribers as the real client would deliver them.
+/// With the `data` feature, they are also applied to the state
+/// returned by [`MockClient::items`].
+///
+/// This is for testing only, and is not meant to be used in production.
+#[derive(Debug, Clone)]
+pub struct MockClient {
+    tx: broadcast::Sender<Event>,
+    items: TrayItemMap,
+    activations: Arc<Mutex<Vec<ActivateRequest>>>,
+}
+
+impl Default for MockClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockClient {
+    /// Creates a mock client with no items.
+    #[must_use]
+    pub fn new() -> Self {
+        let (tx, _) = broadcast::channel(DEFAULT_CAPACITY);
+
+        Self {
+            tx,
+            items: TrayItemMap::new(),
+            activations: Arc::default(),
+        }
+    }
+
+    /// Sends `event` to all subscribers,
+    /// as if it had been received from the bus.
+    ///
+    /// Like the real client, an add for an item which already exists
+    /// or a remove for an item which does not exist is not sent,
+    /// and events are still applied to the state when there are no subscribers.
+    ///
+    /// # Errors
+    ///
+    /// Never errors, but returns a result in case sending can fail in future.
+    pub fn send(&self, event: Event) -> Result<()> {
+        match self.items.send(&self.tx, event) {
+            // the real client keeps its own receiver, so never fails here
+            Ok(_) | Err(Error::EventSend(_)) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Subscribes to the events broadcast channel,
+    /// returning a new receiver.
+    #[must_use]
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    /// Gets all current items, including their menus if present.
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn items(&self) -> Arc<Mutex<crate::data::BaseMap>> {
+        self.items.get_map()
+    }
+
+    /// Gets the current state of a single item, including its menu if present.
+    #[cfg(feature = "data")]
+    #[must_use]
+    pub fn get_item(&self, address: &str) -> Option<(StatusNotifierItem, Option<TrayMenu>)> {
+        self.items.get(address)
+    }
+
+    /// Records the activation without sending it anywhere.
+    ///
+    /// Use [`MockClient::activations`] to check what was requested.
+    ///
+    /// # Errors
+    ///
+    /// Never errors, but returns a result to match the real client.
+    #[allow(clippy::unused_async)]
+    pub async fn activate(&self, req: ActivateRequest) -> Result<()> {
+        self.activations.lock_or_recover().push(req);
+        Ok(())
+    }
+
+    /// Records the activation without sending it anywhere,
+    /// reporting it as acknowledged.
+    ///
+    /// # Errors
+    ///
+    /// Never errors, but returns a result to match the real client.
+    #[allow(clippy::unused_async)]
+    pub async fn activate_with_outcome(&self, req: ActivateRequest) -> Result<ActivateOutcome> {
+        self.activations.lock_or_recover().push(req);
+        Ok(ActivateOutcome::Acknowledged)
+    }
+
+    /// Gets every activation requested so far, in order.
+    #[must_use]
+    pub fn activations(&self) -> Vec<ActivateRequest> {
+        self.activations.lock_or_recover().clone()
+    }
+}
+
+#[cfg(all(test, feature = "data"))]
+mod tests {
+    use super::*;
+    use crate::client::UpdateEvent;
+
+    #[tokio::test]
+    async fn events_and_activations() {
+        let client = MockClient::new();
+        let mut rx = client.subscribe();
+
+        let dest = ":1.1".to_string();
+        let item = StatusNotifierItem {
+            id: "test".to_string(),
+            ..Default::default()
+        };
+
+        let events = [
+            Event::Add {
+                address: dest.clone(),
+                item: Box::new(item),
+                initial: false,
+            },
+            Event::Update(dest.clone(), UpdateEvent::Title(Some("Test".to_string()))),
+        ];
+        for event in events {
+            client.send(event).expect("send should succeed");
+        }
+
+        assert!(matches!(rx.try_recv(), Ok(Event::Add { .. })));
+        assert!(matches!(rx.try_recv(), Ok(Event::Update(..))));
+
+        let (item, _) = client.get_item(&dest).expect("item should be tracked");
+        assert_eq!(Some("Test"), item.title.as_deref());
+
+        client
+            .activate(ActivateRequest::default_activate(&dest, 0, 0))
+            .await
+            .expect("activate should succeed");
+        assert!(matches!(
+            client.activations().as_slice(),
+            [ActivateRequest::Default { address, .. }] if *address == dest
+        ));
+    }
+
+    #[test]
+    fn send_without_subscribers() {
+        let client = MockClient::new();
+
+        let event = Event::Add {
+            address: ":1.1".to_string(),
+            item: Box::default(),
+            initial: false,
+        };
+        client.send(event).expect("send should succeed");
+        assert!(client.get_item(":1.1").is_some());
+    }
+}
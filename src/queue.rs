@@ -0,0 +1,250 @@
+use crate::client::{Event, UpdateEvent};
+use crate::error::Result;
+use crate::sync::MutexExt;
+use std::collections::VecDeque;
+use std::mem::Discriminant;
+use std::sync::Mutex;
+use tokio::sync::{broadcast, Notify};
+
+/// Queue in front of the events channel,
+/// which coalesces updates while a receiver is full.
+///
+/// Once the slowest receiver is `capacity` events behind,
+/// updates are held back instead of being sent.
+/// A held back update replaces any earlier one
+/// of the same kind for the same item,
+/// so a receiver which catches up only gets the latest of each.
+///
+/// Incremental menu updates ([`UpdateEvent::MenuDiff`] and [`UpdateEvent::MenuSubtree`])
+/// and all other events are never coalesced.
+/// They are sent straight away, after any held back updates so the order is kept,
+/// even if that means a receiver lags.
+#[derive(Debug)]
+pub(crate) struct EventQueue {
+    capacity: usize,
+    inner: Mutex<Inner>,
+    /// Notified when an update is held back.
+    queued: Notify,
+}
+
+#[derive(Debug)]
+struct Inner {
+    pending: VecDeque<Event>,
+    /// Keeps the channel open when there are no other receivers.
+    ///
+    /// This is drained on every send, so it never counts as falling behind.
+    keepalive: broadcast::Receiver<Event>,
+}
+
+/// The kind of update an event is coalesced by.
+#[derive(Debug, PartialEq, Eq)]
+struct CoalesceKey<'a> {
+    address: &'a str,
+    kind: Discriminant<UpdateEvent>,
+    /// The property name of [`UpdateEvent::Other`],
+    /// as each property is a different kind of update.
+    name: Option<&'a str>,
+}
+
+impl<'a> CoalesceKey<'a> {
+    fn of(event: &'a Event) -> Option<Self> {
+        let Event::Update(address, update) = event else {
+            return None;
+        };
+
+        let name = match update {
+            UpdateEvent::MenuDiff(_) | UpdateEvent::MenuSubtree { .. } => return None,
+            UpdateEvent::Other { name, .. } => Some(name.as_str()),
+            _ => None,
+        };
+
+        Some(Self {
+            address,
+            kind: std::mem::discriminant(update),
+            name,
+        })
+    }
+}
+
+impl EventQueue {
+    /// Creates a queue for a channel of `capacity`,
+    /// taking `keepalive` as a receiver which is never read from elsewhere.
+    pub(crate) fn new(capacity: usize, keepalive: broadcast::Receiver<Event>) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(Inner {
+                pending: VecDeque::new(),
+                keepalive,
+            }),
+            queued: Notify::new(),
+        }
+    }
+
+    /// Sends `event` to `tx`,
+    /// or holds it back if it is an update and a receiver is full.
+    pub(crate) fn send(&self, tx: &broadcast::Sender<Event>, event: Event) -> Result<()> {
+        let mut inner = self.inner.lock_or_recover();
+
+        let Some(key) = CoalesceKey::of(&event) else {
+            for event in inner.pending.drain(..) {
+                tx.send(event)?;
+            }
+
+            tx.send(event)?;
+            return Ok(());
+        };
+
+        if self.flush_locked(&mut inner, tx)? && self.has_room(&mut inner, tx) {
+            tx.send(event)?;
+            return Ok(());
+        }
+
+        inner
+            .pending
+            .retain(|pending| CoalesceKey::of(pending).as_ref() != Some(&key));
+        inner.pending.push_back(event);
+
+        self.queued.notify_one();
+        Ok(())
+    }
+
+    /// Sends as many held back updates as there is room for.
+    ///
+    /// Returns whether all of them have been sent.
+    pub(crate) fn flush(&self, tx: &broadcast::Sender<Event>) -> Result<bool> {
+        let mut inner = self.inner.lock_or_recover();
+        self.flush_locked(&mut inner, tx)
+    }
+
+    /// Waits until an update is held back.
+    pub(crate) async fn queued(&self) {
+        self.queued.notified().await;
+    }
+
+    fn flush_locked(&self, inner: &mut Inner, tx: &broadcast::Sender<Event>) -> Result<bool> {
+        while !inner.pending.is_empty() && self.has_room(inner, tx) {
+            if let Some(event) = inner.pending.pop_front() {
+                tx.send(event)?;
+            }
+        }
+
+        Ok(inner.pending.is_empty())
+    }
+
+    /// Checks whether every receiver has room for another event.
+    fn has_room(&self, inner: &mut Inner, tx: &broadcast::Sender<Event>) -> bool {
+        while !matches!(
+            inner.keepalive.try_recv(),
+            Err(broadcast::error::TryRecvError::Empty | broadcast::error::TryRecvError::Closed)
+        ) {}
+
+        tx.len() < self.capacity
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::item::Status;
+    use crate::menu::MenuDiff;
+
+    fn title(address: &str, title: usize) -> Event {
+        Event::Update(
+            address.to_string(),
+            UpdateEvent::Title(Some(title.to_string())),
+        )
+    }
+
+    fn received(rx: &mut broadcast::Receiver<Event>) -> Vec<Event> {
+        std::iter::from_fn(|| rx.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn coalesces_updates_while_full() {
+        let (tx, keepalive) = broadcast::channel(4);
+        let queue = EventQueue::new(4, keepalive);
+        let mut rx = tx.subscribe();
+
+        for i in 0..10 {
+            queue
+                .send(&tx, title(":1.1", i))
+                .expect("send should succeed");
+        }
+        queue
+            .send(
+                &tx,
+                Event::Update(":1.1".to_string(), UpdateEvent::Status(Status::Active)),
+            )
+            .expect("send should succeed");
+        queue
+            .send(&tx, title(":1.2", 0))
+            .expect("send should succeed");
+
+        // the first updates fill the channel
+        let events = received(&mut rx);
+        assert_eq!(4, events.len());
+        assert!(matches!(
+            &events[3],
+            Event::Update(_, UpdateEvent::Title(Some(title))) if title == "3"
+        ));
+
+        // then only the latest of each kind is sent once there is room
+        assert!(queue.flush(&tx).expect("flush should succeed"));
+        let events = received(&mut rx);
+        assert_eq!(3, events.len());
+        assert!(matches!(
+            &events[0],
+            Event::Update(dest, UpdateEvent::Title(Some(title))) if dest == ":1.1" && title == "9"
+        ));
+        assert!(matches!(
+            &events[1],
+            Event::Update(_, UpdateEvent::Status(Status::Active))
+        ));
+        assert!(matches!(
+            &events[2],
+            Event::Update(dest, UpdateEvent::Title(_)) if dest == ":1.2"
+        ));
+    }
+
+    #[test]
+    fn menu_diffs_not_coalesced() {
+        let (tx, keepalive) = broadcast::channel(4);
+        let queue = EventQueue::new(4, keepalive);
+        let mut rx = tx.subscribe();
+
+        let diff = |id| {
+            Event::Update(
+                ":1.1".to_string(),
+                UpdateEvent::MenuDiff(vec![MenuDiff {
+                    id,
+                    ..Default::default()
+                }]),
+            )
+        };
+
+        for i in 0..6 {
+            queue
+                .send(&tx, title(":1.1", i))
+                .expect("send should succeed");
+        }
+        queue.send(&tx, diff(1)).expect("send should succeed");
+        queue.send(&tx, diff(2)).expect("send should succeed");
+
+        // the receiver lags rather than losing a diff,
+        // and the held back title is sent before the diffs
+        assert!(matches!(
+            rx.try_recv(),
+            Err(broadcast::error::TryRecvError::Lagged(_))
+        ));
+        let events = received(&mut rx);
+        assert!(matches!(
+            events.as_slice(),
+            [
+                ..,
+                Event::Update(_, UpdateEvent::Title(Some(title))),
+                Event::Update(_, UpdateEvent::MenuDiff(first)),
+                Event::Update(_, UpdateEvent::MenuDiff(second)),
+            ] if title == "5" && first[0].id == 1 && second[0].id == 2
+        ));
+    }
+}
@@ -0,0 +1,125 @@
+//! Serializes byte buffers as base64 strings rather than arrays of numbers,
+//! which keeps image data compact in formats such as JSON.
+//!
+//! Use with `#[serde(with = "crate::serde_base64")]`
+//! on `Vec<u8>` fields, or any level of `Option` wrapping them.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+pub(crate) trait Base64Bytes: Sized {
+    fn serialize_base64<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error>;
+    fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+impl Base64Bytes for Vec<u8> {
+    fn serialize_base64<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&STANDARD.encode(self))
+    }
+
+    fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let encoded = String::deserialize(deserializer)?;
+        STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+    }
+}
+
+impl<T: Base64Bytes> Base64Bytes for Option<T> {
+    fn serialize_base64<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Some(value) => serializer.serialize_some(&Wrapper(value)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    fn deserialize_base64<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(Option::<Wrapper<T>>::deserialize(deserializer)?.map(|wrapper| wrapper.0))
+    }
+}
+
+/// Allows nested values to be passed back through `serialize_some`/`Option::deserialize`.
+struct Wrapper<T>(T);
+
+impl<T: Base64Bytes> Serialize for Wrapper<&T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.serialize_base64(serializer)
+    }
+}
+
+impl<'de, T: Base64Bytes> Deserialize<'de> for Wrapper<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        T::deserialize_base64(deserializer).map(Wrapper)
+    }
+}
+
+pub(crate) fn serialize<T: Base64Bytes, S: Serializer>(
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    value.serialize_base64(serializer)
+}
+
+pub(crate) fn deserialize<'de, T: Base64Bytes, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    T::deserialize_base64(deserializer)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::client::{Event, UpdateEvent};
+    use crate::item::IconPixmap;
+    use crate::menu::{MenuItem, TrayMenu};
+
+    #[test]
+    fn pixmap_as_base64() {
+        let event = Event::Update(
+            ":1.1".to_string(),
+            UpdateEvent::Icon {
+                icon_name: None,
+                icon_pixmap: Some(vec![IconPixmap {
+                    width: 1,
+                    height: 1,
+                    pixels: vec![0xff, 0x00, 0x80, 0x40],
+                }]),
+            },
+        );
+
+        let json = serde_json::to_string(&event).expect("event should serialize");
+        assert!(json.contains(r#""pixels":"/wCAQA==""#), "{json}");
+
+        let event = serde_json::from_str::<Event>(&json).expect("event should deserialize");
+        let Event::Update(_, UpdateEvent::Icon { icon_pixmap, .. }) = event else {
+            panic!("unexpected event: {event:?}");
+        };
+        let icon_pixmap = icon_pixmap.expect("pixmap should be present");
+        assert_eq!(vec![0xff, 0x00, 0x80, 0x40], icon_pixmap[0].pixels);
+    }
+
+    #[test]
+    fn menu_icon_data_as_base64() {
+        let menu = TrayMenu {
+            revision: 1,
+            submenus: vec![
+                MenuItem {
+                    id: 1,
+                    icon_data: Some(vec![1, 2, 3]),
+                    ..Default::default()
+                },
+                MenuItem {
+                    id: 2,
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let json = serde_json::to_string(&menu).expect("menu should serialize");
+        assert!(json.contains(r#""icon_data":"AQID""#), "{json}");
+        assert!(json.contains(r#""icon_data":null"#), "{json}");
+
+        let menu = serde_json::from_str::<TrayMenu>(&json).expect("menu should deserialize");
+        assert_eq!(Some(vec![1, 2, 3]), menu.submenus[0].icon_data);
+        assert_eq!(None, menu.submenus[1].icon_data);
+    }
+}
@@ -0,0 +1,46 @@
+use std::sync::{Mutex, MutexGuard};
+use tracing::warn;
+
+/// Extension for locking a [`Mutex`] without panicking.
+pub(crate) trait MutexExt<T> {
+    /// Locks the mutex, blocking until it is available.
+    ///
+    /// If another thread panicked while holding the lock,
+    /// a warning is logged and the lock is taken anyway
+    /// rather than cascading the panic to every other task.
+    fn lock_or_recover(&self) -> MutexGuard<'_, T>;
+}
+
+impl<T> MutexExt<T> for Mutex<T> {
+    fn lock_or_recover(&self) -> MutexGuard<'_, T> {
+        self.lock().unwrap_or_else(|err| {
+            warn!("recovering from poisoned mutex");
+            self.clear_poison();
+            err.into_inner()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn recover_poisoned() {
+        let mutex = Arc::new(Mutex::new(1));
+
+        let _ = std::thread::spawn({
+            let mutex = mutex.clone();
+            move || {
+                let _lock = mutex.lock().expect("mutex lock should succeed");
+                panic!("poison the mutex");
+            }
+        })
+        .join();
+
+        assert!(mutex.is_poisoned());
+        assert_eq!(1, *mutex.lock_or_recover());
+        assert!(!mutex.is_poisoned());
+    }
+}
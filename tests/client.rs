@@ -0,0 +1,1071 @@
+mod common;
+
+use common::{wait_for, Bus, FakeItem, ICON_THEME_PATH};
+use std::time::Duration;
+use system_tray::client::{
+    ActivateOutcome, ActivateRequest, Client, CoordinateSpace, Event, ScrollOrientation,
+    UpdateEvent, DEFAULT_REQUEST_TIMEOUT,
+};
+use system_tray::error::Error;
+use system_tray::item::{Category, Status};
+use system_tray::menu::{MenuEventType, TextDirection, TrayMenu};
+use zbus::fdo::DBusProxy;
+use zbus::names::BusName;
+
+#[tokio::test]
+async fn drop_releases_host_name() {
+    let bus = Bus::start();
+
+    let connection = bus.connect().await;
+    let client = Client::builder()
+        .connection(connection.clone())
+        .build()
+        .await
+        .expect("client to start");
+
+    let host_name = client.host_name();
+    let host_name = BusName::try_from(host_name.as_str()).expect("valid host name");
+
+    assert_eq!(
+        connection.unique_name(),
+        client.connection().unique_name(),
+        "client to use the given connection"
+    );
+
+    let dbus = DBusProxy::new(&connection).await.expect("dbus proxy");
+    assert!(dbus
+        .name_has_owner(host_name.clone())
+        .await
+        .expect("name query"));
+
+    drop(client);
+
+    // the name is released by a background task
+    let released = tokio::time::timeout(Duration::from_secs(1), async {
+        while dbus
+            .name_has_owner(host_name.clone())
+            .await
+            .expect("name query")
+        {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await;
+
+    assert!(released.is_ok(), "host name was not released");
+}
+
+// the blocking client is dropped from within this runtime,
+// which needs other worker threads to keep driving the connection
+#[tokio::test(flavor = "multi_thread")]
+async fn blocking_drop_releases_host_name() {
+    let bus = Bus::start();
+
+    let connection = bus.connect().await;
+    let builder = Client::builder()
+        .connection(connection.clone())
+        .host_name_prefix("org.example.BlockingHost");
+
+    let client =
+        tokio::task::spawn_blocking(|| system_tray::blocking::Client::with_builder(builder))
+            .await
+            .expect("blocking task to complete")
+            .expect("client to start");
+
+    let pid = std::process::id();
+    let host_name =
+        BusName::try_from(format!("org.example.BlockingHost-{pid}-1")).expect("valid host name");
+
+    let dbus = DBusProxy::new(&connection).await.expect("dbus proxy");
+    assert!(dbus
+        .name_has_owner(host_name.clone())
+        .await
+        .expect("name query"));
+
+    // the name is released before the drop returns
+    drop(client);
+
+    assert!(!dbus.name_has_owner(host_name).await.expect("name query"));
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn blocking_events_batched() {
+    let bus = Bus::start();
+
+    let builder = Client::builder()
+        .connection(bus.connect().await)
+        .capacity(2);
+
+    let client =
+        tokio::task::spawn_blocking(|| system_tray::blocking::Client::with_builder(builder))
+            .await
+            .expect("blocking task to complete")
+            .expect("client to start");
+
+    let mut items = vec![];
+    for id in ["first", "second", "third", "fourth"] {
+        items.push(FakeItem::register(&bus, id, &[]).await);
+    }
+    let address = items[3].address();
+
+    // give the events time to overflow the channel
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let (events, skipped, _client) = tokio::task::spawn_blocking(move || {
+        let mut client = client;
+        let events = client.next_event().expect("client to be running");
+        let skipped = client.skipped_events();
+        (events, skipped, client)
+    })
+    .await
+    .expect("blocking task to complete");
+
+    assert!(events.len() > 1, "events already received to be batched");
+    assert!(events
+        .iter()
+        .any(|event| matches!(event, Event::Add { address: dest, .. } if *dest == address)));
+    assert!(skipped > 0, "skipped events to be counted");
+}
+
+#[tokio::test]
+async fn host_name_prefix() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .host_name_prefix("org.example.TestHost")
+        .build()
+        .await
+        .expect("client to start");
+
+    let pid = std::process::id();
+    assert_eq!(format!("org.example.TestHost-{pid}-1"), client.host_name());
+
+    let res = Client::builder()
+        .connection(bus.connect().await)
+        .host_name_prefix("not a bus name")
+        .build()
+        .await;
+    assert!(matches!(res, Err(Error::InvalidData(_))));
+}
+
+#[tokio::test]
+async fn initial_items_flagged() {
+    let bus = Bus::start();
+
+    // runs the watcher the item registers with
+    let watcher = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = watcher.subscribe();
+
+    let _item = FakeItem::register(&bus, "fake", &[]).await;
+    let event = wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+    assert!(matches!(event, Event::Add { initial: false, .. }));
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .embed_watcher(false)
+        .build()
+        .await
+        .expect("client to start");
+
+    // the initial items are loaded in a task which has not run yet
+    let mut rx = client.subscribe();
+    let event = wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+    assert!(matches!(event, Event::Add { initial: true, .. }));
+}
+
+#[cfg(feature = "data")]
+#[tokio::test]
+async fn initial_items_awaited() {
+    let bus = Bus::start();
+
+    // runs the watcher the item registers with
+    let watcher = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = watcher.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+    wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .embed_watcher(false)
+        .await_initial_items(true)
+        .build()
+        .await
+        .expect("client to start");
+
+    let (item, _) = client
+        .get_item(&item.address())
+        .expect("initial item to be loaded");
+    assert_eq!("fake", item.id);
+}
+
+#[tokio::test]
+async fn item_add_update_remove() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+    let address = item.address();
+
+    let event = wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+    assert!(
+        matches!(event, Event::Add { address: ref dest, ref item, initial: false } if *dest == address && item.id == "fake")
+    );
+
+    item.set_title(Some("Fake Item")).await;
+    let event = wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Title(_)))
+    })
+    .await;
+    assert!(matches!(
+        event,
+        Event::Update(ref dest, UpdateEvent::Title(Some(ref title))) if *dest == address && title == "Fake Item"
+    ));
+
+    item.remove().await;
+    let event = wait_for(&mut rx, |event| matches!(event, Event::Remove(_))).await;
+    assert!(matches!(event, Event::Remove(ref dest) if *dest == address));
+
+    #[cfg(feature = "data")]
+    assert!(client.get_item(&address).is_none());
+}
+
+#[tokio::test]
+async fn items_removed_when_bus_lost() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        // so the item is not removed by its own watch failing first
+        .item_retries(u32::MAX)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+    let address = item.address();
+    wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+
+    drop(bus);
+
+    let event = wait_for(&mut rx, |event| matches!(event, Event::Remove(_))).await;
+    assert!(matches!(event, Event::Remove(dest) if dest == address));
+}
+
+#[cfg(feature = "data")]
+#[tokio::test]
+async fn replaced_item_watched() {
+    let bus = Bus::start();
+
+    // owns the watcher name until it releases it
+    let first_connection = bus.connect().await;
+    let _first = Client::builder()
+        .connection(first_connection.clone())
+        .build()
+        .await
+        .expect("client to start");
+
+    let connection = bus.connect().await;
+    let client = Client::builder()
+        .connection(connection.clone())
+        .coalesce_readded_items(Duration::from_secs(5))
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &["Open"]).await;
+    let address = item.address();
+    wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Menu(_)))
+    })
+    .await;
+
+    let watcher = BusName::try_from("org.kde.StatusNotifierWatcher").expect("valid name");
+    DBusProxy::new(&first_connection)
+        .await
+        .expect("dbus proxy")
+        .release_name(watcher.clone().try_into().expect("well-known name"))
+        .await
+        .expect("to release watcher name");
+
+    // wait for the client to take over the watcher before re-registering
+    let dbus = DBusProxy::new(&connection).await.expect("dbus proxy");
+    let own_name = connection.unique_name().expect("unique name").to_string();
+    let taken_over = tokio::time::timeout(Duration::from_secs(5), async {
+        while dbus
+            .get_name_owner(watcher.clone())
+            .await
+            .map_or(true, |owner| owner.as_str() != own_name)
+        {
+            tokio::task::yield_now().await;
+        }
+    })
+    .await;
+    assert!(taken_over.is_ok(), "client did not take over the watcher");
+
+    item.reregister_as("other").await;
+
+    let event = wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+    assert!(
+        matches!(event, Event::Add { address: ref dest, ref item, .. } if *dest == address && item.id == "other")
+    );
+
+    // the menu of the new item is fetched by a new watch
+    let event = wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Menu(_)))
+    })
+    .await;
+    assert!(matches!(event, Event::Update(dest, _) if dest == address));
+}
+
+#[tokio::test]
+async fn title_empty_or_unset() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+    wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+
+    for title in [Some(""), None] {
+        item.set_title(title).await;
+        let event = wait_for(&mut rx, |event| {
+            matches!(event, Event::Update(_, UpdateEvent::Title(_)))
+        })
+        .await;
+        let Event::Update(_, UpdateEvent::Title(received)) = event else {
+            unreachable!()
+        };
+        assert_eq!(title.map(ToString::to_string), received);
+    }
+}
+
+#[tokio::test]
+async fn item_properties_fetched() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+    item.set_title(Some("Fake Item")).await;
+
+    let properties = client
+        .fetch_item_properties(&item.address())
+        .await
+        .expect("properties to be fetched");
+    assert_eq!("fake", properties.id);
+    assert_eq!(Some("Fake Item"), properties.title.as_deref());
+}
+
+#[tokio::test]
+async fn registered_items_by_category() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+
+    let items = client
+        .registered_items_by_category(Category::ApplicationStatus)
+        .await
+        .expect("items to be fetched");
+    assert_eq!(1, items.len());
+    assert!(items[0].starts_with(&item.address()));
+
+    // served from the cache
+    let items = client
+        .registered_items_by_category(Category::Communications)
+        .await
+        .expect("items to be fetched");
+    assert!(items.is_empty());
+}
+
+#[tokio::test]
+async fn wait_for_item() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let res = client
+        .wait_for_item("fake", Duration::from_millis(100))
+        .await;
+    assert!(matches!(res, Err(Error::ItemTimeout(ref id)) if id == "fake"));
+
+    let (item, _) = tokio::join!(
+        client.wait_for_item("fake", Duration::from_secs(5)),
+        FakeItem::register(&bus, "fake", &[]),
+    );
+    assert_eq!("fake", item.expect("item to be found").id);
+}
+
+#[tokio::test]
+async fn menu_layout_updates() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &["Open", "Quit"]).await;
+    let address = item.address();
+
+    let labels = |menu: &TrayMenu| {
+        menu.submenus
+            .iter()
+            .map(|item| item.label.clone().unwrap_or_default())
+            .collect::<Vec<_>>()
+    };
+
+    let event = wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Menu(_)))
+    })
+    .await;
+    let Event::Update(dest, UpdateEvent::Menu(menu)) = event else {
+        unreachable!()
+    };
+    assert_eq!(address, dest);
+    assert_eq!(vec!["Open", "Quit"], labels(&menu));
+    assert_eq!(vec![ICON_THEME_PATH], menu.icon_theme_path);
+
+    item.set_labels(&["Open", "Recent", "Quit"]).await;
+    let event = wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Menu(_)))
+    })
+    .await;
+    let Event::Update(_, UpdateEvent::Menu(menu)) = event else {
+        unreachable!()
+    };
+    assert_eq!(vec!["Open", "Recent", "Quit"], labels(&menu));
+    assert_eq!(2, menu.revision);
+
+    // the cache follows the events
+    #[cfg(feature = "data")]
+    {
+        let (_, menu) = client.get_item(&address).expect("item to be tracked");
+        assert_eq!(
+            vec!["Open", "Recent", "Quit"],
+            labels(&menu.expect("menu to be cached"))
+        );
+    }
+}
+
+#[tokio::test]
+async fn layout_reset_applied() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .layout_debounce(Duration::ZERO)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &["Open"]).await;
+    let is_menu = |event: &Event| matches!(event, Event::Update(_, UpdateEvent::Menu(_)));
+
+    wait_for(&mut rx, is_menu).await;
+    item.set_labels(&["Open", "Quit"]).await;
+    wait_for(&mut rx, is_menu).await;
+
+    // the revision only drops by one, but the menu is still refetched
+    item.reset_labels(&["Restarted"]).await;
+
+    let Event::Update(_, UpdateEvent::Menu(menu)) = wait_for(&mut rx, is_menu).await else {
+        unreachable!()
+    };
+    assert_eq!(1, menu.revision);
+    assert_eq!(Some("Restarted"), menu.submenus[0].label.as_deref());
+}
+
+#[tokio::test]
+async fn refresh_menu_keeps_menu_properties() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &["Open"]).await;
+    item.set_text_direction("rtl").await;
+
+    let menu = client
+        .refresh_menu(item.address(), "/MenuBar".to_string())
+        .await
+        .expect("menu to be refreshed");
+    assert_eq!(TextDirection::RightToLeft, menu.text_direction);
+    assert_eq!(vec![ICON_THEME_PATH], menu.icon_theme_path);
+}
+
+#[tokio::test]
+async fn menus_primed() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .prime_menus(true)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &["Open"]).await;
+
+    wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Menu(_)))
+    })
+    .await;
+    assert_eq!(vec![0], item.menu_shown().await);
+}
+
+#[tokio::test]
+async fn menu_text_direction() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &["Open"]).await;
+
+    let event = wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Menu(_)))
+    })
+    .await;
+    let Event::Update(_, UpdateEvent::Menu(menu)) = event else {
+        unreachable!()
+    };
+    assert_eq!(TextDirection::LeftToRight, menu.text_direction);
+
+    item.set_text_direction("rtl").await;
+
+    let event = wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::MenuTextDirection(_)))
+    })
+    .await;
+    assert!(matches!(
+        event,
+        Event::Update(
+            _,
+            UpdateEvent::MenuTextDirection(TextDirection::RightToLeft)
+        )
+    ));
+
+    #[cfg(feature = "data")]
+    {
+        let (_, menu) = client
+            .get_item(&item.address())
+            .expect("item to be tracked");
+        assert_eq!(
+            TextDirection::RightToLeft,
+            menu.expect("menu to be cached").text_direction
+        );
+    }
+}
+
+#[tokio::test]
+async fn menu_events_sent() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &["Open"]).await;
+    let address = item.address();
+
+    for event_id in [MenuEventType::Opened, MenuEventType::Clicked] {
+        client
+            .activate(ActivateRequest::MenuEvent {
+                address: address.clone(),
+                menu_path: "/MenuBar".to_string(),
+                submenu_id: 1,
+                event_id,
+            })
+            .await
+            .expect("event to be sent");
+    }
+
+    assert_eq!(
+        vec![(1, "opened".to_string()), (1, "clicked".to_string())],
+        item.menu_events().await
+    );
+}
+
+#[cfg(feature = "data")]
+#[tokio::test]
+async fn activate_by_shortcut() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &["Open", "Quit"]).await;
+    let address = item.address();
+
+    wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Menu(_)))
+    })
+    .await;
+
+    let activated = client
+        .activate_by_shortcut(&address, &["ctrl".to_string()], "2")
+        .await
+        .expect("event to be sent");
+    assert!(activated);
+
+    let activated = client
+        .activate_by_shortcut(&address, &[], "2")
+        .await
+        .expect("no event to be sent");
+    assert!(!activated);
+
+    assert_eq!(vec![(2, "clicked".to_string())], item.menu_events().await);
+}
+
+#[tokio::test]
+async fn activate_outcome() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &["Open"]).await;
+    let address = item.address();
+
+    let click = |submenu_id| ActivateRequest::MenuItem {
+        address: address.clone(),
+        menu_path: "/MenuBar".to_string(),
+        submenu_id,
+    };
+
+    let outcome = client
+        .activate_with_outcome(click(1))
+        .await
+        .expect("event to be sent");
+    assert_eq!(ActivateOutcome::Acknowledged, outcome);
+
+    let outcome = client
+        .activate_with_outcome(click(2))
+        .await
+        .expect("event to be sent");
+    assert!(matches!(outcome, ActivateOutcome::Rejected(_)));
+
+    // the fake item does not implement `Activate`
+    let outcome = client
+        .activate_with_outcome(ActivateRequest::default_activate(&address, 0, 0))
+        .await
+        .expect("event to be sent");
+    assert!(matches!(outcome, ActivateOutcome::Rejected(_)));
+}
+
+#[tokio::test]
+async fn menu_event_group_sent() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &["Open", "Recent"]).await;
+
+    let not_found = client
+        .menu_event_group(
+            item.address(),
+            "/MenuBar".to_string(),
+            vec![
+                (1, MenuEventType::Closed, 0),
+                (2, MenuEventType::Opened, 0),
+                (3, MenuEventType::Opened, 0),
+            ],
+        )
+        .await
+        .expect("events to be sent");
+
+    assert_eq!(vec![3], not_found);
+    assert_eq!(
+        vec![(1, "closed".to_string()), (2, "opened".to_string())],
+        item.menu_events().await
+    );
+}
+
+#[tokio::test]
+async fn about_to_show_group_sent() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &["Open", "Recent"]).await;
+
+    let (updates_needed, not_found) = client
+        .about_to_show_group(item.address(), "/MenuBar".to_string(), vec![1, 2, 3])
+        .await
+        .expect("group to be shown");
+
+    assert_eq!(vec![1, 2], updates_needed);
+    assert_eq!(vec![3], not_found);
+    assert_eq!(vec![1, 2], item.menu_shown().await);
+}
+
+#[cfg(feature = "data")]
+#[tokio::test]
+async fn snapshot_stream_converges() {
+    use futures_lite::StreamExt;
+
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let mut snapshots = Box::pin(client.snapshot_stream());
+
+    let snapshot = snapshots.next().await.expect("initial snapshot");
+    assert!(snapshot.is_empty());
+
+    let item = FakeItem::register(&bus, "fake", &["Open"]).await;
+    let address = item.address();
+
+    let snapshot = tokio::time::timeout(Duration::from_secs(5), async {
+        loop {
+            let snapshot = snapshots.next().await.expect("client to be running");
+            if snapshot
+                .get(&address)
+                .is_some_and(|(_, menu)| menu.is_some())
+            {
+                break snapshot;
+            }
+        }
+    })
+    .await
+    .expect("timed out waiting for item");
+
+    assert_eq!("fake", snapshot[&address].0.id);
+
+    item.remove().await;
+
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while snapshots
+            .next()
+            .await
+            .expect("client to be running")
+            .contains_key(&address)
+        {}
+    })
+    .await
+    .expect("timed out waiting for removal");
+}
+
+#[tokio::test]
+async fn broken_item_added_as_placeholder() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .request_timeout(Duration::from_millis(200))
+        .item_retries(1)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register_broken(&bus).await;
+    let address = item.address();
+
+    let event = wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+    let Event::Add {
+        address: dest,
+        item,
+        ..
+    } = event
+    else {
+        unreachable!()
+    };
+    assert_eq!(address, dest);
+    assert_eq!(address, item.id);
+    assert_eq!(Status::Unknown, item.status);
+}
+
+#[tokio::test]
+async fn unresponsive_item_does_not_block_others() {
+    let bus = Bus::start();
+
+    // runs the watcher the items register with
+    let watcher = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = watcher.subscribe();
+
+    let _broken = FakeItem::register_unresponsive(&bus).await;
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+
+    let event = tokio::time::timeout(
+        DEFAULT_REQUEST_TIMEOUT / 2,
+        wait_for(&mut rx, |event| matches!(event, Event::Add { .. })),
+    )
+    .await
+    .expect("item to be added promptly");
+    assert!(matches!(event, Event::Add { address, .. } if address == item.address()));
+
+    // the same goes for initial items and `Ready`
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .embed_watcher(false)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let event = tokio::time::timeout(
+        DEFAULT_REQUEST_TIMEOUT * 3 / 2,
+        wait_for(&mut rx, |event| matches!(event, Event::Ready)),
+    )
+    .await
+    .expect("ready to be sent promptly");
+    assert!(matches!(event, Event::Ready));
+
+    #[cfg(feature = "data")]
+    assert!(client.get_item(&item.address()).is_some());
+}
+
+#[tokio::test]
+async fn malformed_item_reported() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register_malformed(&bus).await;
+
+    let event = wait_for(&mut rx, |event| {
+        matches!(event, Event::Add { .. } | Event::Malformed { .. })
+    })
+    .await;
+    let Event::Malformed { address, reason } = event else {
+        panic!("unexpected event: {event:?}");
+    };
+    assert_eq!(item.address(), address);
+    assert!(reason.contains("Id"), "unexpected reason: {reason}");
+}
+
+#[tokio::test]
+async fn failed_menu_watch_disconnects_menu() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .item_retries(1)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register_without_menu(&bus, "fake").await;
+    let address = item.address();
+
+    let event = wait_for(&mut rx, |event| {
+        matches!(
+            event,
+            Event::Update(_, UpdateEvent::Menu(_) | UpdateEvent::MenuDisconnect)
+        )
+    })
+    .await;
+    assert!(matches!(
+        event,
+        Event::Update(ref dest, UpdateEvent::MenuDisconnect) if *dest == address
+    ));
+
+    #[cfg(feature = "data")]
+    {
+        let (item, menu) = client.get_item(&address).expect("item to be kept");
+        assert!(item.menu.is_none());
+        assert!(menu.is_none());
+    }
+}
+
+#[tokio::test]
+async fn extension_property_update() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+    let mut rx = client.subscribe();
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+    wait_for(&mut rx, |event| matches!(event, Event::Add { .. })).await;
+
+    item.set_label("42%").await;
+
+    let event = wait_for(&mut rx, |event| {
+        matches!(event, Event::Update(_, UpdateEvent::Other { .. }))
+    })
+    .await;
+    let Event::Update(_, UpdateEvent::Other { name, value }) = event else {
+        unreachable!()
+    };
+    assert_eq!("XAyatanaLabel", name);
+    assert_eq!(Ok("42%"), value.downcast_ref::<&str>());
+}
+
+#[tokio::test]
+async fn scroll_unsupported() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+
+    let res = client
+        .try_scroll(item.address(), 1, ScrollOrientation::Vertical)
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::Unsupported {
+            method: "Scroll",
+            ..
+        })
+    ));
+}
+
+#[tokio::test]
+async fn negative_coordinates_sent() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .coordinate_space(CoordinateSpace::Logical { scale: 2.0 })
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &[]).await;
+
+    client
+        .activate(ActivateRequest::secondary_activate(&item.address(), 5, -1))
+        .await
+        .expect("activation to be sent");
+    assert_eq!(vec![(10, -2)], item.secondary_activations().await);
+
+    // positions which cannot be translated are rejected without sending anything
+    let res = client
+        .activate(ActivateRequest::secondary_activate(
+            &item.address(),
+            i32::MIN,
+            0,
+        ))
+        .await;
+    assert!(matches!(
+        res,
+        Err(Error::InvalidCoordinates {
+            x: i32::MIN,
+            y: 0,
+            ..
+        })
+    ));
+    assert_eq!(1, item.secondary_activations().await.len());
+}
+
+#[tokio::test]
+async fn group_properties_fetched() {
+    let bus = Bus::start();
+
+    let client = Client::builder()
+        .connection(bus.connect().await)
+        .build()
+        .await
+        .expect("client to start");
+
+    let item = FakeItem::register(&bus, "fake", &["Open", "Recent", "Quit"]).await;
+
+    let mut diffs = client
+        .get_group_properties(
+            item.address(),
+            "/MenuBar".to_string(),
+            vec![1, 3],
+            vec!["label".to_string()],
+        )
+        .await
+        .expect("properties to be fetched");
+    diffs.sort_by_key(|diff| diff.id);
+
+    let labels = diffs
+        .iter()
+        .map(|diff| (diff.id, diff.update.label.clone().flatten()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![(1, Some("Open".to_string())), (3, Some("Quit".to_string()))],
+        labels
+    );
+}
@@ -0,0 +1,514 @@
+use std::collections::HashMap;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedObjectPath, OwnedValue, Value};
+use zbus::{interface, Connection};
+
+use super::Bus;
+
+const ITEM_PATH: &str = "/StatusNotifierItem";
+const MENU_PATH: &str = "/MenuBar";
+
+/// The icon theme path advertised by the menu.
+pub const ICON_THEME_PATH: &str = "/opt/fake/icons";
+
+/// A minimal `StatusNotifierItem` with a `DBusMenu`,
+/// served on its own connection to a [`Bus`].
+///
+/// The menu is a flat list of items with the given labels,
+/// with ids starting at 1.
+pub struct FakeItem {
+    connection: Connection,
+}
+
+impl FakeItem {
+    /// Serves a new item with `id` and registers it with the watcher on `bus`.
+    pub async fn register(bus: &Bus, id: &str, labels: &[&str]) -> Self {
+        let item = Self::serve_item(bus, id).await;
+
+        let menu = Menu {
+            revision: 1,
+            labels: labels.iter().map(ToString::to_string).collect(),
+            events: vec![],
+            shown: vec![],
+            text_direction: "ltr".to_string(),
+        };
+
+        item.connection
+            .object_server()
+            .at(MENU_PATH, menu)
+            .await
+            .expect("to serve menu");
+
+        Self::register_connection(item.connection).await
+    }
+
+    /// Serves and registers an item which points to a menu it does not serve,
+    /// so fetching the menu always fails.
+    pub async fn register_without_menu(bus: &Bus, id: &str) -> Self {
+        let item = Self::serve_item(bus, id).await;
+        Self::register_connection(item.connection).await
+    }
+
+    async fn serve_item(bus: &Bus, id: &str) -> Self {
+        let connection = bus.connect().await;
+
+        let item = Item {
+            id: id.to_string(),
+            title: Some(String::new()),
+            label: String::new(),
+            secondary_activations: vec![],
+        };
+
+        connection
+            .object_server()
+            .at(ITEM_PATH, item)
+            .await
+            .expect("to serve item");
+
+        Self { connection }
+    }
+
+    /// Serves and registers an item which is missing its required `Id` property.
+    pub async fn register_malformed(bus: &Bus) -> Self {
+        let connection = bus.connect().await;
+
+        connection
+            .object_server()
+            .at(ITEM_PATH, MalformedItem)
+            .await
+            .expect("to serve item");
+
+        Self::register_connection(connection).await
+    }
+
+    /// Registers an item without serving it,
+    /// like an application which fails to export its properties.
+    pub async fn register_broken(bus: &Bus) -> Self {
+        Self::register_connection(bus.connect().await).await
+    }
+
+    /// Registers an item which never answers requests,
+    /// like an application which has hung.
+    pub async fn register_unresponsive(bus: &Bus) -> Self {
+        let connection = bus.connect().await;
+
+        connection
+            .object_server()
+            .at(ITEM_PATH, UnresponsiveItem)
+            .await
+            .expect("to serve item");
+
+        Self::register_connection(connection).await
+    }
+
+    async fn register_connection(connection: Connection) -> Self {
+        let address = connection
+            .unique_name()
+            .expect("connection to have a unique name")
+            .to_string();
+
+        connection
+            .call_method(
+                Some("org.kde.StatusNotifierWatcher"),
+                "/StatusNotifierWatcher",
+                Some("org.kde.StatusNotifierWatcher"),
+                "RegisterStatusNotifierItem",
+                &(address.as_str(),),
+            )
+            .await
+            .expect("to register item");
+
+        Self { connection }
+    }
+
+    /// Gets the address the client reports the item under.
+    pub fn address(&self) -> String {
+        self.connection
+            .unique_name()
+            .expect("connection to have a unique name")
+            .to_string()
+    }
+
+    /// Changes the item's title and signals the change.
+    ///
+    /// A `None` title is reported as missing when it is fetched.
+    pub async fn set_title(&self, title: Option<&str>) {
+        let item = self
+            .connection
+            .object_server()
+            .interface::<_, Item>(ITEM_PATH)
+            .await
+            .expect("item to be served");
+
+        item.get_mut().await.title = title.map(ToString::to_string);
+        Item::new_title(item.signal_emitter())
+            .await
+            .expect("to signal title");
+    }
+
+    /// Changes the item's id and registers it again,
+    /// like a different application reusing the connection.
+    #[cfg(feature = "data")]
+    pub async fn reregister_as(&self, id: &str) {
+        let item = self
+            .connection
+            .object_server()
+            .interface::<_, Item>(ITEM_PATH)
+            .await
+            .expect("item to be served");
+
+        item.get_mut().await.id = id.to_string();
+
+        let address = self.address();
+        self.connection
+            .call_method(
+                Some("org.kde.StatusNotifierWatcher"),
+                "/StatusNotifierWatcher",
+                Some("org.kde.StatusNotifierWatcher"),
+                "RegisterStatusNotifierItem",
+                &(address.as_str(),),
+            )
+            .await
+            .expect("to register item");
+    }
+
+    /// Changes the item's `XAyatanaLabel` extension property and signals the change.
+    pub async fn set_label(&self, label: &str) {
+        let item = self
+            .connection
+            .object_server()
+            .interface::<_, Item>(ITEM_PATH)
+            .await
+            .expect("item to be served");
+
+        item.get_mut().await.label = label.to_string();
+        Item::x_ayatana_new_label(item.signal_emitter(), label, "")
+            .await
+            .expect("to signal label");
+    }
+
+    /// Replaces the menu's items and signals the layout change.
+    pub async fn set_labels(&self, labels: &[&str]) {
+        let menu = self
+            .connection
+            .object_server()
+            .interface::<_, Menu>(MENU_PATH)
+            .await
+            .expect("menu to be served");
+
+        let revision = {
+            let mut menu = menu.get_mut().await;
+            menu.revision += 1;
+            menu.labels = labels.iter().map(ToString::to_string).collect();
+            menu.revision
+        };
+
+        Menu::layout_updated(menu.signal_emitter(), revision, 0)
+            .await
+            .expect("to signal layout");
+    }
+
+    /// Replaces the menu as a restarted application would,
+    /// starting the revisions over, and signals the change.
+    pub async fn reset_labels(&self, labels: &[&str]) {
+        let menu = self
+            .connection
+            .object_server()
+            .interface::<_, Menu>(MENU_PATH)
+            .await
+            .expect("menu to be served");
+
+        {
+            let mut menu = menu.get_mut().await;
+            menu.revision = 1;
+            menu.labels = labels.iter().map(ToString::to_string).collect();
+        }
+
+        Menu::layout_updated(menu.signal_emitter(), 1, 0)
+            .await
+            .expect("to signal layout");
+    }
+
+    /// Changes the menu's text direction and signals the change.
+    pub async fn set_text_direction(&self, text_direction: &str) {
+        let menu = self
+            .connection
+            .object_server()
+            .interface::<_, Menu>(MENU_PATH)
+            .await
+            .expect("menu to be served");
+
+        let mut lock = menu.get_mut().await;
+        lock.text_direction = text_direction.to_string();
+        lock.text_direction_changed(menu.signal_emitter())
+            .await
+            .expect("to signal text direction");
+    }
+
+    /// Gets the ids and event ids of the menu events received so far.
+    pub async fn menu_events(&self) -> Vec<(i32, String)> {
+        let menu = self
+            .connection
+            .object_server()
+            .interface::<_, Menu>(MENU_PATH)
+            .await
+            .expect("menu to be served");
+
+        let events = menu.get().await.events.clone();
+        events
+    }
+
+    /// Gets the ids `AboutToShow` was called with so far.
+    pub async fn menu_shown(&self) -> Vec<i32> {
+        let menu = self
+            .connection
+            .object_server()
+            .interface::<_, Menu>(MENU_PATH)
+            .await
+            .expect("menu to be served");
+
+        let shown = menu.get().await.shown.clone();
+        shown
+    }
+
+    /// Gets the positions `SecondaryActivate` was called with so far.
+    pub async fn secondary_activations(&self) -> Vec<(i32, i32)> {
+        let item = self
+            .connection
+            .object_server()
+            .interface::<_, Item>(ITEM_PATH)
+            .await
+            .expect("item to be served");
+
+        let activations = item.get().await.secondary_activations.clone();
+        activations
+    }
+
+    /// Disconnects the item from the bus.
+    pub async fn remove(self) {
+        self.connection.close().await.expect("to close connection");
+    }
+}
+
+struct Item {
+    id: String,
+    title: Option<String>,
+    label: String,
+    secondary_activations: Vec<(i32, i32)>,
+}
+
+#[interface(name = "org.kde.StatusNotifierItem")]
+impl Item {
+    #[zbus(property)]
+    fn id(&self) -> String {
+        self.id.clone()
+    }
+
+    #[zbus(property)]
+    fn category(&self) -> &str {
+        "ApplicationStatus"
+    }
+
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        "Active"
+    }
+
+    #[zbus(property)]
+    fn title(&self) -> zbus::fdo::Result<String> {
+        self.title
+            .clone()
+            .ok_or_else(|| zbus::fdo::Error::InvalidArgs("Title is not set".to_string()))
+    }
+
+    #[zbus(property)]
+    fn item_is_menu(&self) -> bool {
+        false
+    }
+
+    #[zbus(property)]
+    fn menu(&self) -> OwnedObjectPath {
+        OwnedObjectPath::try_from(MENU_PATH).expect("valid menu path")
+    }
+
+    #[zbus(property)]
+    fn x_ayatana_label(&self) -> String {
+        self.label.clone()
+    }
+
+    fn secondary_activate(&mut self, x: i32, y: i32) {
+        self.secondary_activations.push((x, y));
+    }
+
+    #[zbus(signal)]
+    async fn new_title(emitter: &SignalEmitter<'_>) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn x_ayatana_new_label(
+        emitter: &SignalEmitter<'_>,
+        label: &str,
+        guide: &str,
+    ) -> zbus::Result<()>;
+}
+
+struct MalformedItem;
+
+#[interface(name = "org.kde.StatusNotifierItem")]
+impl MalformedItem {
+    #[zbus(property)]
+    fn status(&self) -> &str {
+        "Active"
+    }
+}
+
+struct UnresponsiveItem;
+
+#[interface(name = "org.kde.StatusNotifierItem")]
+impl UnresponsiveItem {
+    #[zbus(property)]
+    async fn id(&self) -> String {
+        std::future::pending().await
+    }
+}
+
+type Layout = (i32, HashMap<String, OwnedValue>, Vec<OwnedValue>);
+
+struct Menu {
+    revision: u32,
+    labels: Vec<String>,
+    events: Vec<(i32, String)>,
+    shown: Vec<i32>,
+    text_direction: String,
+}
+
+#[interface(name = "com.canonical.dbusmenu")]
+impl Menu {
+    #[zbus(property)]
+    fn text_direction(&self) -> String {
+        self.text_direction.clone()
+    }
+
+    #[zbus(property)]
+    fn icon_theme_path(&self) -> Vec<String> {
+        vec![ICON_THEME_PATH.to_string()]
+    }
+
+    #[allow(unused_variables)]
+    fn get_layout(
+        &self,
+        parent_id: i32,
+        recursion_depth: i32,
+        property_names: Vec<String>,
+    ) -> (u32, Layout) {
+        let children = (1..)
+            .zip(&self.labels)
+            .map(|(id, label)| {
+                // each item has the shortcut of its position with control
+                let shortcut = vec![vec!["Control".to_string(), id.to_string()]];
+                let props = HashMap::from([
+                    (
+                        "label".to_string(),
+                        OwnedValue::from(zbus::zvariant::Str::from(label.clone())),
+                    ),
+                    (
+                        "shortcut".to_string(),
+                        OwnedValue::try_from(Value::from(shortcut)).expect("valid shortcut"),
+                    ),
+                ]);
+
+                let child: Layout = (id, props, vec![]);
+                OwnedValue::try_from(Value::from(child)).expect("valid layout")
+            })
+            .collect();
+
+        let props = HashMap::from([(
+            "children-display".to_string(),
+            OwnedValue::from(zbus::zvariant::Str::from_static("submenu")),
+        )]);
+
+        (self.revision, (0, props, children))
+    }
+
+    fn get_group_properties(
+        &self,
+        ids: Vec<i32>,
+        property_names: Vec<String>,
+    ) -> Vec<(i32, HashMap<String, OwnedValue>)> {
+        ids.into_iter()
+            .filter_map(|id| {
+                let label = self.labels.get(usize::try_from(id).ok()?.checked_sub(1)?)?;
+
+                let props = HashMap::from([(
+                    "label".to_string(),
+                    OwnedValue::from(zbus::zvariant::Str::from(label.clone())),
+                )])
+                .into_iter()
+                .filter(|(name, _)| property_names.is_empty() || property_names.contains(name))
+                .collect();
+
+                Some((id, props))
+            })
+            .collect()
+    }
+
+    fn about_to_show(&mut self, id: i32) -> bool {
+        self.shown.push(id);
+        false
+    }
+
+    /// Every item which exists needs updating.
+    fn about_to_show_group(&mut self, ids: Vec<i32>) -> (Vec<i32>, Vec<i32>) {
+        let (found, not_found) = ids
+            .into_iter()
+            .partition(|id| (1..=self.labels.len()).contains(&(*id as usize)));
+
+        self.shown.extend(&found);
+        (found, not_found)
+    }
+
+    #[allow(unused_variables)]
+    fn event(
+        &mut self,
+        id: i32,
+        event_id: &str,
+        data: OwnedValue,
+        timestamp: u32,
+    ) -> zbus::fdo::Result<()> {
+        if !(1..=self.labels.len()).contains(&(id as usize)) {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "no item with id {id}"
+            )));
+        }
+
+        self.events.push((id, event_id.to_string()));
+        Ok(())
+    }
+
+    fn event_group(&mut self, events: Vec<(i32, String, OwnedValue, u32)>) -> Vec<i32> {
+        let mut not_found = vec![];
+
+        for (id, event_id, _, _) in events {
+            if (1..=self.labels.len()).contains(&(id as usize)) {
+                self.events.push((id, event_id));
+            } else {
+                not_found.push(id);
+            }
+        }
+
+        not_found
+    }
+
+    #[zbus(signal)]
+    async fn layout_updated(
+        emitter: &SignalEmitter<'_>,
+        revision: u32,
+        parent: i32,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    async fn items_properties_updated(
+        emitter: &SignalEmitter<'_>,
+        updated: Vec<(i32, HashMap<String, OwnedValue>)>,
+        removed: Vec<(i32, Vec<String>)>,
+    ) -> zbus::Result<()>;
+}
@@ -0,0 +1,83 @@
+mod fake_item;
+
+pub use fake_item::{FakeItem, ICON_THEME_PATH};
+
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+use std::time::Duration;
+use system_tray::client::Event;
+use tokio::sync::broadcast;
+use zbus::Connection;
+
+/// A private session bus, running for as long as this is alive.
+pub struct Bus {
+    daemon: Child,
+    address: String,
+}
+
+impl Bus {
+    /// Starts a new bus.
+    ///
+    /// # Panics
+    ///
+    /// If `dbus-daemon` is not installed.
+    pub fn start() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("dbus-daemon to be installed to run the integration tests");
+
+        let stdout = daemon.stdout.take().expect("stdout to be piped");
+
+        let mut address = String::new();
+        BufReader::new(stdout)
+            .read_line(&mut address)
+            .expect("to read bus address");
+
+        Self {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+
+    /// Opens a new connection to the bus.
+    pub async fn connect(&self) -> Connection {
+        zbus::connection::Builder::address(self.address.as_str())
+            .expect("valid bus address")
+            .build()
+            .await
+            .expect("to connect to bus")
+    }
+}
+
+impl Drop for Bus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
+
+/// Waits for the next event matching `predicate`, skipping any others.
+///
+/// # Panics
+///
+/// If no matching event is received within a few seconds.
+pub async fn wait_for(
+    rx: &mut broadcast::Receiver<Event>,
+    predicate: impl Fn(&Event) -> bool,
+) -> Event {
+    let event = async {
+        loop {
+            let event = rx.recv().await.expect("client to be running");
+            if predicate(&event) {
+                break event;
+            }
+        }
+    };
+
+    tokio::time::timeout(Duration::from_secs(5), event)
+        .await
+        .expect("timed out waiting for event")
+}